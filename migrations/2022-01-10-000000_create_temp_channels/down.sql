@@ -0,0 +1 @@
+DROP TABLE IF EXISTS temp_channels;
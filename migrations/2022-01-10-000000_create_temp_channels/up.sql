@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS temp_channels (
+    id BIGINT PRIMARY KEY,
+    owner_id BIGINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    last_occupied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS temp_channels_owner_id_idx ON temp_channels (owner_id);
@@ -0,0 +1,215 @@
+use crate::config::Config;
+use crate::context::ErisContext;
+use crate::desertbus::{DesertBus, DesertBusSettings, Progress, Shift};
+use crate::extract::Extract;
+use crate::models::State;
+use crate::try_crosspost::TryCrosspost;
+use crate::typemap_keys::PgPool;
+use anyhow::{Context, Error};
+use chrono::{DateTime, Utc};
+use separator::FixedPlaceSeparatable;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::error;
+
+const STATE_KEY: &str = "eris.announcements.desertbus";
+
+/// What has been announced about the run that started at `start`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Announced {
+    start: DateTime<Utc>,
+    milestone: Option<f64>,
+    hours: i64,
+    shift: Option<Shift>,
+}
+
+/// The messages to post since `previous`, and what will have been announced after posting them.
+/// Nothing is posted the first time a run is seen so that a restart or a new run doesn't post
+/// everything that already happened.
+fn announcements(
+    progress: &Progress,
+    milestones: &[f64],
+    previous: Option<&Announced>,
+) -> (Vec<String>, Announced) {
+    let current = Announced {
+        start: progress.start(),
+        milestone: milestones
+            .iter()
+            .rev()
+            .find(|&&milestone| milestone <= progress.money_raised)
+            .copied(),
+        hours: progress.hours,
+        shift: if progress.has_started() && !progress.is_over() {
+            Some(Shift::at(progress.now))
+        } else {
+            None
+        },
+    };
+
+    let previous = match previous {
+        Some(previous) if previous.start == current.start => previous,
+        _ => return (vec![], current),
+    };
+
+    let mut messages = vec![];
+
+    if current.milestone > previous.milestone {
+        if let Some(milestone) = current.milestone {
+            messages.push(format!(
+                "Desert Bus for Hope just passed ${}!",
+                milestone.separated_string_with_fixed_place(2)
+            ));
+        }
+    }
+
+    if current.hours > previous.hours {
+        messages.push(format!(
+            "Hour {} bought! ${} until the next one.",
+            current.hours,
+            progress.next_hour().separated_string_with_fixed_place(2)
+        ));
+    }
+
+    if current.shift.is_some() && current.shift != previous.shift {
+        if let Some(shift) = current.shift {
+            messages.push(format!("Shift change: {} is now on the bus.", shift));
+        }
+    }
+
+    // Totals occasionally get corrected downwards, which shouldn't lead to announcing things twice.
+    let announced = Announced {
+        milestone: if current.milestone > previous.milestone {
+            current.milestone
+        } else {
+            previous.milestone
+        },
+        hours: current.hours.max(previous.hours),
+        shift: current.shift.or(previous.shift),
+        ..current
+    };
+
+    (messages, announced)
+}
+
+async fn inner(ctx: &ErisContext) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+    let config = data.extract::<Config>()?;
+    let channel = match config.desertbus_channel {
+        Some(channel) => channel,
+        None => return Ok(()),
+    };
+
+    let now = Utc::now();
+    let settings = DesertBusSettings::load(&data)?;
+    if !settings.in_announce_window(now) {
+        return Ok(());
+    }
+
+    let money_raised = data.extract::<DesertBus>()?.money_raised().await?;
+    let progress = settings.progress(money_raised, now);
+
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a DB connection from the connection pool")?;
+
+    let previous = State::get::<Announced, _>(STATE_KEY, &conn)
+        .context("failed to get the announced Desert Bus progress")?;
+    let (messages, announced) =
+        announcements(&progress, &config.desertbus_milestones, previous.as_ref());
+
+    for message in messages {
+        channel
+            .say(ctx, &message)
+            .await
+            .context("failed to send the Desert Bus announcement")?
+            .try_crosspost(ctx)
+            .await
+            .context("failed to crosspost the Desert Bus announcement")?;
+    }
+
+    if previous.as_ref() != Some(&announced) {
+        State::set(STATE_KEY, &announced, &conn)
+            .context("failed to save the announced Desert Bus progress")?;
+    }
+
+    Ok(())
+}
+
+pub async fn post_desertbus_progress(ctx: ErisContext) {
+    let mut timer = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        timer.tick().await;
+
+        if let Err(error) = inner(&ctx).await {
+            error!(?error, "Failed to post the Desert Bus progress");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::announcements;
+    use crate::desertbus::DesertBusSettings;
+    use chrono::{DateTime, Utc};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn progress() {
+        let settings = DesertBusSettings {
+            start: DateTime::parse_from_rfc3339("2022-11-11T14:00:00-08:00").unwrap(),
+            first_hour: 1.0,
+            multiplier: 2.0,
+            ..DesertBusSettings::default()
+        };
+        let milestones = [10.0, 20.0, 50.0];
+
+        // The first poll only records where the run is.
+        let (messages, announced) =
+            announcements(&settings.progress(10.0, at("2022-11-11T21:00:00Z")), &milestones, None);
+        assert!(messages.is_empty());
+        assert_eq!(announced.milestone, Some(10.0));
+        assert_eq!(announced.hours, 3);
+        assert_eq!(announced.shift, None);
+
+        // The run starts.
+        let (messages, announced) = announcements(
+            &settings.progress(12.0, at("2022-11-11T22:01:00Z")),
+            &milestones,
+            Some(&announced),
+        );
+        assert_eq!(messages, vec!["Shift change: Alpha Flight is now on the bus."]);
+
+        let (messages, announced) = announcements(
+            &settings.progress(35.0, at("2022-11-12T02:00:00Z")),
+            &milestones,
+            Some(&announced),
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "Desert Bus for Hope just passed $20.00!",
+                "Hour 5 bought! $28.00 until the next one.",
+                "Shift change: Night Watch is now on the bus.",
+            ]
+        );
+
+        // A correction doesn't lead to announcing the milestone again.
+        let (messages, announced) = announcements(
+            &settings.progress(19.0, at("2022-11-12T02:01:00Z")),
+            &milestones,
+            Some(&announced),
+        );
+        assert!(messages.is_empty());
+        let (messages, _) = announcements(
+            &settings.progress(21.0, at("2022-11-12T02:02:00Z")),
+            &milestones,
+            Some(&announced),
+        );
+        assert!(messages.is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::config::{Config, FeedConfig};
+use crate::context::ErisContext;
+use crate::extract::Extract;
+use crate::feed::{Entry, Feed, Feeds};
+use crate::models::State;
+use crate::try_crosspost::TryCrosspost;
+use crate::typemap_keys::PgPool;
+use anyhow::{Context, Error};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use serenity::prelude::TypeMap;
+use std::time::Duration;
+use tracing::error;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Where a feed was left off: the newest publication time that has been announced and the entries
+/// that can't be told apart by time alone, the ones published at that time or with no time at all.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Cursor {
+    published: Option<DateTime<FixedOffset>>,
+    seen: Vec<String>,
+}
+
+/// What an entry is known by: its ID, or its link if it doesn't have one.
+fn entry_key(entry: &Entry) -> Option<&str> {
+    entry.id.as_deref().or(entry.link.as_deref())
+}
+
+impl Cursor {
+    /// A cursor past everything in `feed`.
+    fn at_end(feed: &Feed) -> Cursor {
+        let mut cursor = Cursor::default();
+        for entry in &feed.entries {
+            cursor.mark(entry);
+        }
+        cursor.prune(feed);
+        cursor
+    }
+
+    fn is_new(&self, entry: &Entry) -> bool {
+        let key = match entry_key(entry) {
+            Some(key) => key,
+            None => return false,
+        };
+        let seen = self.seen.iter().any(|seen| seen == key);
+        match (entry.published, self.published) {
+            (Some(published), Some(last_published)) => {
+                published > last_published || (published == last_published && !seen)
+            }
+            _ => !seen,
+        }
+    }
+
+    fn mark(&mut self, entry: &Entry) {
+        if let Some(key) = entry_key(entry) {
+            if !self.seen.iter().any(|seen| seen == key) {
+                self.seen.push(String::from(key));
+            }
+        }
+        if entry.published > self.published {
+            self.published = entry.published;
+        }
+    }
+
+    /// Forgets the entries that are no longer needed to tell what's new.
+    fn prune(&mut self, feed: &Feed) {
+        let published = self.published;
+        self.seen.retain(|key| {
+            feed.entries.iter().any(|entry| {
+                entry_key(entry) == Some(key)
+                    && (entry.published.is_none() || entry.published >= published)
+            })
+        });
+    }
+}
+
+/// Entries that haven't been announced, oldest first. Entries without a publication time come
+/// last, in the order opposite to the feed's, which is usually newest first.
+fn new_entries<'a>(feed: &'a Feed, cursor: &Cursor) -> Vec<&'a Entry> {
+    let (mut dated, mut undated): (Vec<_>, Vec<_>) = feed
+        .entries
+        .iter()
+        .filter(|entry| cursor.is_new(entry))
+        .partition(|entry| entry.published.is_some());
+    dated.sort_by_key(|entry| entry.published);
+    undated.reverse();
+    dated.extend(undated);
+    dated
+}
+
+async fn announce(
+    ctx: &ErisContext,
+    data: &TypeMap,
+    feeds: &Feeds,
+    feed_config: &FeedConfig,
+) -> Result<(), Error> {
+    let state_key = format!("eris.announcements.feeds.{}.cursor", feed_config.name);
+    let cursor = {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a DB connection from the connection pool")?;
+        State::get::<Cursor, _>(&state_key, &conn).context("failed to get the feed cursor")?
+    };
+
+    let feed = feeds.fetch(feed_config.url.as_str()).await.context("failed to fetch the feed")?;
+
+    let mut cursor = match cursor {
+        Some(cursor) => cursor,
+        None => {
+            // Don't send an avalanche of posts when first activated.
+            let conn = data
+                .extract::<PgPool>()?
+                .get()
+                .context("failed to get a DB connection from the connection pool")?;
+            State::set(&state_key, Cursor::at_end(&feed), &conn)
+                .context("failed to set the feed cursor")?;
+            return Ok(());
+        }
+    };
+
+    let title = feed.title.as_deref().unwrap_or(&feed_config.name);
+    for entry in new_entries(&feed, &cursor) {
+        if let Some(link) = entry.link.as_deref().or(entry.id.as_deref()) {
+            let message = format!("New post from {}: {}", title, link);
+            for channel in &feed_config.channels {
+                channel
+                    .say(ctx, &message)
+                    .await
+                    .context("failed to send the announcement message")?
+                    .try_crosspost(ctx)
+                    .await
+                    .context("failed to crosspost the announcement message")?;
+            }
+        }
+
+        cursor.mark(entry);
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a DB connection from the connection pool")?;
+        State::set(&state_key, &cursor, &conn).context("failed to set the feed cursor")?;
+    }
+
+    let pruned = {
+        let mut pruned = cursor.clone();
+        pruned.prune(&feed);
+        pruned
+    };
+    if pruned != cursor {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a DB connection from the connection pool")?;
+        State::set(&state_key, &pruned, &conn).context("failed to set the feed cursor")?;
+    }
+
+    Ok(())
+}
+
+async fn inner(ctx: &ErisContext) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+    let feeds = data.extract::<Feeds>()?;
+
+    for feed_config in &data.extract::<Config>()?.feeds {
+        if let Err(error) = announce(ctx, &data, feeds, feed_config).await {
+            error!(?error, feed = feed_config.name.as_str(), "Failed to announce a feed");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn post_feeds(ctx: ErisContext) {
+    let mut timer = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        timer.tick().await;
+
+        if let Err(error) = inner(&ctx).await {
+            error!(?error, "Failed to announce new feed entries");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new_entries, Cursor};
+    use crate::feed::{Entry, Feed};
+    use chrono::DateTime;
+
+    fn ids<'a>(entries: &[&'a Entry]) -> Vec<&'a str> {
+        entries.iter().map(|entry| entry.id.as_deref().unwrap()).collect()
+    }
+
+    fn entry(id: &str, published: Option<&str>) -> Entry {
+        Entry {
+            id: Some(id.into()),
+            title: None,
+            link: None,
+            published: published.map(|published| DateTime::parse_from_rfc3339(published).unwrap()),
+        }
+    }
+
+    #[test]
+    fn only_new_entries() {
+        let feed = Feed::parse(include_str!("../../fixtures/feeds/youtube.xml")).unwrap();
+        // A cursor that has announced everything up to `published`.
+        let at = |published| {
+            let published = Some(DateTime::parse_from_rfc3339(published).unwrap());
+            let mut cursor = Cursor::default();
+            for entry in feed.entries.iter().filter(|entry| entry.published <= published) {
+                cursor.mark(entry);
+            }
+            cursor
+        };
+
+        let entries = new_entries(&feed, &at("2021-12-01T00:00:00Z"));
+        assert_eq!(ids(&entries), vec!["yt:video:aaaaaaaaaaa", "yt:video:bbbbbbbbbbb"]);
+
+        let entries = new_entries(&feed, &at("2021-12-18T20:00:00Z"));
+        assert_eq!(ids(&entries), vec!["yt:video:bbbbbbbbbbb"]);
+
+        let entries = new_entries(&feed, &at("2021-12-20T20:00:00Z"));
+        assert!(entries.is_empty());
+
+        assert!(new_entries(&feed, &Cursor::at_end(&feed)).is_empty());
+    }
+
+    #[test]
+    fn same_time_and_undated() {
+        let mut feed = Feed {
+            title: None,
+            entries: vec![
+                entry("undated-1", None),
+                entry("a", Some("2021-12-20T20:00:00Z")),
+                entry("old", Some("2021-12-01T20:00:00Z")),
+            ],
+        };
+        let mut cursor = Cursor::at_end(&feed);
+        assert_eq!(cursor.seen, vec!["undated-1", "a"]);
+        cursor.mark(&feed.entries[1]);
+        assert_eq!(cursor.seen, vec!["undated-1", "a"]);
+
+        // Another entry at the same time and a new undated one.
+        feed.entries.insert(0, entry("b", Some("2021-12-20T20:00:00Z")));
+        feed.entries.insert(0, entry("undated-2", None));
+        let entries = new_entries(&feed, &cursor);
+        assert_eq!(ids(&entries), vec!["b", "undated-2"]);
+
+        for entry in entries {
+            cursor.mark(entry);
+        }
+        assert!(new_entries(&feed, &cursor).is_empty());
+
+        // Entries that have dropped out of the feed or are older than the cursor are forgotten.
+        feed.entries.retain(|entry| entry.id.as_deref() != Some("undated-1"));
+        cursor.prune(&feed);
+        assert_eq!(cursor.seen, vec!["a", "b", "undated-2"]);
+    }
+}
@@ -0,0 +1,267 @@
+use crate::config::Config;
+use crate::context::ErisContext;
+use crate::extract::Extract;
+use crate::mastodon::{full_acct, split_acct, Mastodon, Status};
+use crate::models::State;
+use crate::try_crosspost::TryCrosspost;
+use crate::typemap_keys::PgPool;
+use anyhow::{Context, Error};
+use serenity::model::id::ChannelId;
+use serenity::prelude::TypeMap;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{error, info};
+
+struct WatchedAccount {
+    instance: String,
+    id: String,
+    channels: Vec<ChannelId>,
+}
+
+/// Looks up the watched accounts that haven't been found yet, keyed by their `user@instance`.
+/// Accounts that can't be looked up, say because their instance is down, are tried again on the
+/// next pass.
+async fn lookup_missing(
+    ctx: &ErisContext,
+    accounts: &mut HashMap<String, WatchedAccount>,
+) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+    let mastodon = data.extract::<Mastodon>()?;
+
+    for (acct, channels) in &data.extract::<Config>()?.mastodon_users {
+        if accounts.contains_key(acct) {
+            continue;
+        }
+
+        let instance = match split_acct(acct) {
+            Ok((_, instance)) => instance,
+            Err(error) => {
+                error!(?error, acct = acct.as_str(), "Invalid Mastodon account");
+                continue;
+            }
+        };
+        match mastodon.lookup_account(acct).await {
+            Ok(account) => {
+                accounts.insert(
+                    acct.clone(),
+                    WatchedAccount {
+                        instance: instance.into(),
+                        id: account.id,
+                        channels: channels.clone(),
+                    },
+                );
+            }
+            Err(error) => {
+                error!(?error, acct = acct.as_str(), "Failed to look up a Mastodon account")
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the visible text of an HTML status starts with a mention.
+fn starts_with_mention(content: &str) -> bool {
+    let mut in_tag = false;
+    for c in content.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if in_tag || c.is_whitespace() => (),
+            _ => return c == '@',
+        }
+    }
+    false
+}
+
+/// Whether `status`, as fetched from `instance`, should be announced.
+fn should_announce(
+    status: &Status,
+    instance: &str,
+    accounts: &HashMap<String, WatchedAccount>,
+) -> bool {
+    // Account IDs are local to the instance, so go through the mentions to find who's replied to.
+    let is_reply_to_watched = |id: &String| {
+        *id == status.account.id
+            || status
+                .mentions
+                .iter()
+                .find(|mention| mention.id == *id)
+                .map(|mention| accounts.contains_key(&full_acct(&mention.acct, instance)))
+                .unwrap_or(false)
+    };
+
+    // (Non-reply status or a reply to an account we're watching) and (a boost or doesn't start with a user mention)
+    status.in_reply_to_account_id.as_ref().map(is_reply_to_watched).unwrap_or(true)
+        && (status.reblog.is_some() || !starts_with_mention(&status.content))
+}
+
+/// Orders Mastodon IDs, which are decimal numbers that don't fit into a `u64` on every instance.
+fn id_key(id: &str) -> (usize, &str) {
+    (id.len(), id)
+}
+
+async fn announce(
+    ctx: &ErisContext,
+    data: &TypeMap,
+    acct: &str,
+    account: &WatchedAccount,
+    accounts: &HashMap<String, WatchedAccount>,
+) -> Result<(), Error> {
+    let state_key = format!("eris.announcements.mastodon.{}.last_status_id", acct);
+    let last_status_id = {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a DB connection from the connection pool")?;
+
+        State::get::<String, _>(&state_key, &conn).context("failed to get the last status ID")?
+    };
+
+    let mastodon = data.extract::<Mastodon>()?;
+
+    let mut statuses = mastodon
+        .account_statuses(&account.instance, &account.id, 40, last_status_id.as_deref())
+        .await
+        .context("failed to fetch new statuses")?;
+
+    // Don't send an avalanche of statuses when first activated.
+    if last_status_id.is_some() {
+        statuses.sort_by(|a, b| id_key(&a.id).cmp(&id_key(&b.id)));
+        for status in &statuses {
+            if should_announce(status, &account.instance, accounts) {
+                let url = status
+                    .url
+                    .as_deref()
+                    .or_else(|| status.reblog.as_ref().and_then(|reblog| reblog.url.as_deref()));
+                if let Some(url) = url {
+                    let name = if status.account.display_name.is_empty() {
+                        &status.account.username
+                    } else {
+                        &status.account.display_name
+                    };
+                    let message = format!("New post from {}: {}", name, url);
+                    let boosted_acct = status
+                        .reblog
+                        .as_ref()
+                        .map(|reblog| full_acct(&reblog.account.acct, &account.instance));
+                    for channel in &account.channels {
+                        if let Some(boosted) =
+                            boosted_acct.as_ref().and_then(|acct| accounts.get(acct))
+                        {
+                            if boosted.channels.contains(channel) {
+                                info!(
+                                    channel = channel.0,
+                                    msg = message.as_str(),
+                                    "Skipping posting a boost because the target already gets posted to this channel"
+                                );
+                                continue;
+                            }
+                        }
+                        channel
+                            .say(ctx, &message)
+                            .await
+                            .context("failed to send the announcement message")?
+                            .try_crosspost(ctx)
+                            .await
+                            .context("failed to crosspost the announcement message")?;
+                    }
+                }
+            }
+
+            let conn = data
+                .extract::<PgPool>()?
+                .get()
+                .context("failed to get a DB connection from the connection pool")?;
+            State::set(&state_key, &status.id, &conn)
+                .context("failed to set the new last status ID")?;
+        }
+    } else {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a DB connection from the connection pool")?;
+
+        let last_status_id =
+            statuses.iter().map(|status| &status.id[..]).max_by_key(|id| id_key(id)).unwrap_or("0");
+        State::set(&state_key, last_status_id, &conn)
+            .context("failed to set the new last status ID")?;
+    }
+
+    Ok(())
+}
+
+async fn inner(ctx: &ErisContext, accounts: &HashMap<String, WatchedAccount>) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+
+    for (acct, account) in accounts {
+        if let Err(error) = announce(ctx, &data, acct, account, accounts).await {
+            error!(?error, acct = acct.as_str(), "Failed to announce new Mastodon statuses");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn post_statuses(ctx: ErisContext) {
+    let mut accounts = HashMap::new();
+
+    let mut timer = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        timer.tick().await;
+
+        if let Err(error) = lookup_missing(&ctx, &mut accounts).await {
+            error!(?error, "Failed to look up the Mastodon accounts");
+        }
+
+        if let Err(error) = inner(&ctx, &accounts).await {
+            error!(?error, "Failed to announce new Mastodon statuses");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{should_announce, starts_with_mention, WatchedAccount};
+    use crate::mastodon::Status;
+    use std::collections::HashMap;
+
+    #[test]
+    fn mentions() {
+        assert!(starts_with_mention(
+            r#"<p><span class="h-card"><a href="https://mastodon.example/@someone" class="u-url mention">@<span>someone</span></a></span> hi</p>"#
+        ));
+        assert!(!starts_with_mention("<p>Hi @someone</p>"));
+        assert!(!starts_with_mention(""));
+    }
+
+    #[test]
+    fn filtering() {
+        let statuses = serde_json::from_str::<Vec<Status>>(include_str!(
+            "../../fixtures/mastodon/statuses.json"
+        ))
+        .unwrap();
+
+        let mut accounts = HashMap::new();
+        for (acct, id) in
+            &[("loadingreadyrun@mastodon.example", "1"), ("desertbus@other.example", "2")]
+        {
+            accounts.insert(
+                acct.to_string(),
+                WatchedAccount {
+                    instance: acct.split('@').nth(1).unwrap().into(),
+                    id: id.to_string(),
+                    channels: vec![],
+                },
+            );
+        }
+
+        let announced = statuses
+            .iter()
+            .filter(|status| should_announce(status, "mastodon.example", &accounts))
+            .map(|status| &status.id[..])
+            .collect::<Vec<_>>();
+        assert_eq!(announced, vec!["105", "103", "101"]);
+    }
+}
@@ -1,5 +1,15 @@
+mod desertbus;
+mod feeds;
+mod mastodon;
+mod reminders;
+mod schedule_changes;
 mod stream_up;
 mod twitter;
 
-pub use self::stream_up::stream_up;
+pub use self::desertbus::post_desertbus_progress;
+pub use self::feeds::post_feeds;
+pub use self::mastodon::post_statuses;
+pub use self::reminders::post_reminders;
+pub use self::schedule_changes::post_schedule_changes;
+pub use self::stream_up::update_stream_up_announcement;
 pub use self::twitter::post_tweets;
@@ -0,0 +1,182 @@
+use crate::config::Config;
+use crate::context::ErisContext;
+use crate::extract::Extract;
+use crate::google::calendar::{Event, LRR};
+use crate::google::Calendar;
+use crate::models::State;
+use crate::try_crosspost::TryCrosspost;
+use crate::typemap_keys::PgPool;
+use anyhow::{Context, Error};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use serenity::utils::MessageBuilder;
+use std::collections::HashMap;
+use tracing::error;
+use url::Url;
+
+/// Maps the IDs of the events that have been reminded about to the start time at the time.
+const STATE_KEY: &str = "eris.announcements.reminders.sent";
+
+/// Events starting within `lead` that haven't been reminded about at their current start time.
+fn due_reminders<'a>(
+    events: &'a [Event],
+    sent: &HashMap<String, DateTime<FixedOffset>>,
+    now: DateTime<Utc>,
+    lead: Duration,
+) -> Vec<&'a Event> {
+    events
+        .iter()
+        .filter(|event| now < event.start && event.start <= now + lead)
+        .filter(|event| sent.get(&event.id) != Some(&event.start))
+        .collect()
+}
+
+fn reminder_message(event: &Event, rescheduled: bool) -> String {
+    let mut message = MessageBuilder::new();
+    if rescheduled {
+        message.push("Rescheduled: ");
+    }
+    message
+        .push_bold_safe(&event.summary)
+        .push(" starts <t:")
+        .push(event.start.timestamp())
+        .push(":R>");
+
+    if let Some(ref location) = event.location {
+        // Suppress the embed if the location is a link.
+        if Url::parse(location).is_ok() {
+            message.push(" at <").push_safe(location).push(">");
+        } else {
+            message.push(" at ").push_safe(location);
+        }
+    }
+
+    if let Some(ref desc) = event.description {
+        message.push(": ").push_safe(Calendar::format_description(desc));
+    }
+
+    message.build()
+}
+
+async fn inner(ctx: &ErisContext) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+    let config = data.extract::<Config>()?;
+    let calendar = data.extract::<Calendar>()?;
+
+    let now = Utc::now();
+    let events = calendar
+        .get_upcoming_events(LRR, now)
+        .await
+        .context("failed to get the upcoming events")?;
+
+    let mut sent = {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a DB connection from the connection pool")?;
+
+        State::get::<HashMap<String, DateTime<FixedOffset>>, _>(STATE_KEY, &conn)
+            .context("failed to get the sent reminders")?
+            .unwrap_or_default()
+    };
+
+    let due = due_reminders(&events, &sent, now, config.stream_reminder);
+    let stale = sent.values().any(|start| *start < now);
+    if due.is_empty() && !stale {
+        return Ok(());
+    }
+
+    // Record every reminder that went out, even if a later one fails, so none are sent twice.
+    for event in due {
+        let message = reminder_message(event, sent.contains_key(&event.id));
+        let message = match config.announcements.say(ctx, &message).await {
+            Ok(message) => message,
+            Err(error) => {
+                error!(?error, event.id = event.id.as_str(), "Failed to send the reminder");
+                continue;
+            }
+        };
+        sent.insert(event.id.clone(), event.start);
+
+        if let Err(error) = message.try_crosspost(ctx).await {
+            error!(?error, event.id = event.id.as_str(), "Failed to crosspost the reminder");
+        }
+    }
+
+    // Forget about events that have already started.
+    sent.retain(|_, start| *start >= now);
+
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a DB connection from the connection pool")?;
+    State::set(STATE_KEY, &sent, &conn).context("failed to save the sent reminders")?;
+
+    Ok(())
+}
+
+pub async fn post_reminders(ctx: ErisContext) {
+    let mut timer = tokio::time::interval(std::time::Duration::from_secs(60));
+
+    loop {
+        timer.tick().await;
+
+        if let Err(error) = inner(&ctx).await {
+            error!(?error, "Failed to post the stream reminders");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{due_reminders, reminder_message};
+    use crate::google::calendar::Event;
+    use chrono::{DateTime, Duration, Utc};
+    use std::collections::HashMap;
+
+    fn event(id: &str, start: &str) -> Event {
+        let start = DateTime::parse_from_rfc3339(start).unwrap();
+        Event {
+            id: id.into(),
+            start,
+            summary: format!("Stream {}", id),
+            end: start + Duration::hours(2),
+            location: None,
+            description: None,
+        }
+    }
+
+    #[test]
+    fn due() {
+        let now = DateTime::parse_from_rfc3339("2021-12-20T19:50:00Z").unwrap().with_timezone(&Utc);
+        let events = vec![
+            event("started", "2021-12-20T19:00:00Z"),
+            event("soon", "2021-12-20T20:00:00Z"),
+            event("reminded", "2021-12-20T20:00:00Z"),
+            event("moved", "2021-12-20T20:05:00Z"),
+            event("later", "2021-12-20T22:00:00Z"),
+        ];
+        let mut sent = HashMap::new();
+        sent.insert(String::from("reminded"), events[2].start);
+        // Reminded about when it was still at 19:55.
+        sent.insert(String::from("moved"), events[3].start - Duration::minutes(10));
+
+        let due = due_reminders(&events, &sent, now, Duration::minutes(15));
+        assert_eq!(
+            due.iter().map(|event| &event.id[..]).collect::<Vec<_>>(),
+            vec!["soon", "moved"]
+        );
+    }
+
+    #[test]
+    fn message() {
+        let mut event = event("soon", "2021-12-20T20:00:00Z");
+        event.location = Some(String::from("https://twitch.tv/loadingreadyrun"));
+        event.description = Some(String::from("Hades\nA roguelike with a *lot* of dying"));
+
+        assert_eq!(
+            reminder_message(&event, false),
+            "**Stream soon** starts <t:1640030400:R> at <https://twitch.tv/loadingreadyrun>: A roguelike with a \\*lot\\* of dying. Game: Hades"
+        );
+        assert!(reminder_message(&event, true).starts_with("Rescheduled: "));
+    }
+}
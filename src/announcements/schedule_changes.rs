@@ -0,0 +1,310 @@
+use crate::config::Config;
+use crate::context::ErisContext;
+use crate::extract::Extract;
+use crate::google::calendar::{Event, LRR, MAX_RESULTS};
+use crate::google::Calendar;
+use crate::models::State;
+use crate::typemap_keys::PgPool;
+use anyhow::{Context, Error};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::utils::MessageBuilder;
+use std::time::Duration;
+use tracing::error;
+
+const STATE_KEY: &str = "eris.announcements.schedule_changes.snapshot";
+const POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SnapshotEvent {
+    id: String,
+    summary: String,
+    start: DateTime<FixedOffset>,
+}
+
+/// The upcoming events as of the last poll.
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    events: Vec<SnapshotEvent>,
+    /// When the calendar returned as many events as it would, events after the last one weren't
+    /// seen and can't be compared.
+    complete_until: Option<DateTime<FixedOffset>>,
+}
+
+impl Snapshot {
+    fn new(events: &[Event]) -> Snapshot {
+        Snapshot {
+            events: events
+                .iter()
+                .map(|event| SnapshotEvent {
+                    id: event.id.clone(),
+                    summary: event.summary.clone(),
+                    start: event.start,
+                })
+                .collect(),
+            complete_until: if events.len() < MAX_RESULTS {
+                None
+            } else {
+                events.last().map(|event| event.start)
+            },
+        }
+    }
+
+    fn covers(&self, start: DateTime<FixedOffset>) -> bool {
+        self.complete_until.map(|end| start <= end).unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct ScheduleDiff<'a> {
+    added: Vec<&'a SnapshotEvent>,
+    moved: Vec<(&'a SnapshotEvent, &'a SnapshotEvent)>,
+    removed: Vec<&'a SnapshotEvent>,
+}
+
+impl ScheduleDiff<'_> {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.moved.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// `found` are the events that went missing from `new` as looked up one by one, since they might
+/// just have moved out of view.
+fn diff<'a>(
+    old: &'a Snapshot,
+    new: &'a Snapshot,
+    found: &'a [SnapshotEvent],
+    now: DateTime<Utc>,
+) -> ScheduleDiff<'a> {
+    let mut diff = ScheduleDiff::default();
+
+    for event in &new.events {
+        match old.events.iter().find(|old_event| old_event.id == event.id) {
+            Some(old_event) if old_event.start != event.start => {
+                diff.moved.push((old_event, event))
+            }
+            Some(_) => (),
+            // Otherwise it just came into view.
+            None if old.covers(event.start) => diff.added.push(event),
+            None => (),
+        }
+    }
+
+    for old_event in &old.events {
+        // Events that have started drop out of the upcoming events on their own.
+        if old_event.start > now
+            && new.covers(old_event.start)
+            && new.events.iter().all(|event| event.id != old_event.id)
+        {
+            match found.iter().find(|event| event.id == old_event.id) {
+                Some(event) if event.start != old_event.start => {
+                    diff.moved.push((old_event, event))
+                }
+                Some(_) => (),
+                None => diff.removed.push(old_event),
+            }
+        }
+    }
+
+    diff
+}
+
+fn push_event<'a>(
+    message: &'a mut MessageBuilder,
+    event: &SnapshotEvent,
+) -> &'a mut MessageBuilder {
+    message.push_bold_safe(&event.summary).push(" <t:").push(event.start.timestamp()).push(":F>")
+}
+
+fn format_diff(diff: &ScheduleDiff) -> String {
+    let mut message = MessageBuilder::new();
+    message.push("Schedule changes:");
+
+    for event in &diff.added {
+        message.push("\nAdded: ");
+        push_event(&mut message, event);
+    }
+
+    for (old_event, event) in &diff.moved {
+        message
+            .push("\nMoved: ")
+            .push_bold_safe(&event.summary)
+            .push(" <t:")
+            .push(old_event.start.timestamp())
+            .push(":F> → <t:")
+            .push(event.start.timestamp())
+            .push(":F>");
+    }
+
+    for event in &diff.removed {
+        message.push("\nRemoved: ");
+        push_event(&mut message, event);
+    }
+
+    message.build()
+}
+
+async fn inner(ctx: &ErisContext) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+    let config = data.extract::<Config>()?;
+    let channel = match config.schedule_changes_channel {
+        Some(channel) => channel,
+        None => return Ok(()),
+    };
+    let calendar = data.extract::<Calendar>()?;
+
+    let now = Utc::now();
+    let events = calendar
+        .get_upcoming_events(LRR, now)
+        .await
+        .context("failed to get the upcoming events")?;
+    let snapshot = Snapshot::new(&events);
+
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a DB connection from the connection pool")?;
+
+    let previous = State::get::<Snapshot, _>(STATE_KEY, &conn)
+        .context("failed to get the previous schedule snapshot")?;
+
+    if let Some(ref previous) = previous {
+        let missing = diff(previous, &snapshot, &[], now)
+            .removed
+            .into_iter()
+            .map(|event| event.id.clone())
+            .collect::<Vec<_>>();
+        let mut found = vec![];
+        for id in missing {
+            let event = calendar
+                .get_event(LRR, &id, config.timezone)
+                .await
+                .context("failed to look up a missing event")?;
+            if let Some(event) = event {
+                found.push(SnapshotEvent { id, summary: event.summary, start: event.start });
+            }
+        }
+
+        let diff = diff(previous, &snapshot, &found, now);
+        if !diff.is_empty() {
+            channel
+                .say(ctx, format_diff(&diff))
+                .await
+                .context("failed to send the schedule changes")?;
+        }
+    }
+
+    State::set(STATE_KEY, &snapshot, &conn).context("failed to save the schedule snapshot")?;
+
+    Ok(())
+}
+
+pub async fn post_schedule_changes(ctx: ErisContext) {
+    let mut timer = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        timer.tick().await;
+
+        if let Err(error) = inner(&ctx).await {
+            error!(?error, "Failed to post the schedule changes");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, format_diff, Snapshot, SnapshotEvent};
+    use chrono::{DateTime, Utc};
+
+    fn event(id: &str, start: &str) -> SnapshotEvent {
+        SnapshotEvent {
+            id: id.into(),
+            summary: format!("Stream {}", id),
+            start: DateTime::parse_from_rfc3339(start).unwrap(),
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let now = DateTime::parse_from_rfc3339("2021-12-20T12:00:00Z").unwrap().with_timezone(&Utc);
+        let old = Snapshot {
+            events: vec![
+                event("started", "2021-12-20T11:00:00Z"),
+                event("same", "2021-12-20T20:00:00Z"),
+                event("moved", "2021-12-21T20:00:00Z"),
+                event("removed", "2021-12-22T20:00:00Z"),
+                event("last", "2021-12-24T20:00:00Z"),
+            ],
+            complete_until: Some(DateTime::parse_from_rfc3339("2021-12-24T20:00:00Z").unwrap()),
+        };
+        let new = Snapshot {
+            events: vec![
+                event("same", "2021-12-20T20:00:00Z"),
+                event("moved", "2021-12-21T21:00:00Z"),
+                event("added", "2021-12-23T20:00:00Z"),
+                event("last", "2021-12-24T20:00:00Z"),
+                event("in view", "2021-12-25T20:00:00Z"),
+            ],
+            complete_until: None,
+        };
+
+        let diff = diff(&old, &new, &[], now);
+        assert_eq!(diff.added, vec![&new.events[2]]);
+        assert_eq!(diff.moved, vec![(&old.events[2], &new.events[1])]);
+        assert_eq!(diff.removed, vec![&old.events[3]]);
+
+        assert_eq!(
+            format_diff(&diff),
+            "Schedule changes:\n\
+             Added: **Stream added** <t:1640289600:F>\n\
+             Moved: **Stream moved** <t:1640116800:F> → <t:1640120400:F>\n\
+             Removed: **Stream removed** <t:1640203200:F>"
+        );
+    }
+
+    #[test]
+    fn out_of_view() {
+        let now = DateTime::parse_from_rfc3339("2021-12-20T12:00:00Z").unwrap().with_timezone(&Utc);
+        let old = Snapshot {
+            events: vec![
+                event("first", "2021-12-20T20:00:00Z"),
+                event("pushed", "2021-12-22T20:00:00Z"),
+            ],
+            complete_until: None,
+        };
+        let new = Snapshot {
+            events: vec![event("first", "2021-12-20T20:00:00Z")],
+            complete_until: Some(DateTime::parse_from_rfc3339("2021-12-20T20:00:00Z").unwrap()),
+        };
+
+        assert!(diff(&old, &new, &[], now).is_empty());
+    }
+
+    #[test]
+    fn moved_out_of_view() {
+        let now = DateTime::parse_from_rfc3339("2021-12-20T12:00:00Z").unwrap().with_timezone(&Utc);
+        let old = Snapshot {
+            events: vec![
+                event("first", "2021-12-20T20:00:00Z"),
+                event("postponed", "2021-12-21T20:00:00Z"),
+                event("last", "2021-12-22T20:00:00Z"),
+            ],
+            complete_until: None,
+        };
+        let new = Snapshot {
+            events: vec![
+                event("first", "2021-12-20T20:00:00Z"),
+                event("last", "2021-12-22T20:00:00Z"),
+            ],
+            complete_until: Some(DateTime::parse_from_rfc3339("2021-12-22T20:00:00Z").unwrap()),
+        };
+
+        let diff_without = diff(&old, &new, &[], now);
+        assert_eq!(diff_without.removed, vec![&old.events[1]]);
+
+        let found = vec![event("postponed", "2021-12-30T20:00:00Z")];
+        let diff = diff(&old, &new, &found, now);
+        assert_eq!(diff.moved, vec![(&old.events[1], &found[0])]);
+        assert!(diff.removed.is_empty());
+    }
+}
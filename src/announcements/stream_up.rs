@@ -1,17 +1,29 @@
 use crate::config::Config;
 use crate::context::ErisContext;
 use crate::extract::Extract;
-use crate::models::{Game, GameEntry, Show};
+use crate::models::{Game, GameEntry, Show, State, User};
 use crate::rpc::LRRbot;
+use crate::time::HumanReadable;
 use crate::try_crosspost::TryCrosspost;
+use crate::twitch::helix::{GameId, UserId};
+use crate::twitch::Helix;
 use crate::typemap_keys::PgPool;
 use anyhow::{Context, Error};
-use chrono::{DateTime, FixedOffset};
+use chrono::{DateTime, FixedOffset, Utc};
 use diesel::OptionalExtension;
 use eris_macros::rpc_handler;
-use serde::Deserialize;
-use std::fmt::{self, Display};
-use tracing::error;
+use serde::{Deserialize, Serialize};
+use serenity::builder::CreateEmbed;
+use serenity::model::id::{ChannelId, MessageId};
+use serenity::prelude::TypeMap;
+use serenity::utils::{Colour, MessageBuilder};
+use std::cmp;
+use std::time::Duration;
+use tracing::{error, info};
+
+const STATE_KEY: &str = "eris.announcements.stream_up.message";
+const UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+const BOX_ART_SIZE: &str = "285x380";
 
 #[derive(Deserialize)]
 pub struct Channel {
@@ -24,41 +36,114 @@ pub struct Channel {
     pub url: String,
 }
 
-struct StreamUp {
-    channel: Channel,
-    what: String,
-}
-
-impl Display for StreamUp {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(&self.channel.display_name.as_ref().unwrap_or(&self.channel.name))?;
-        f.write_str(" is live with ")?;
-        f.write_str(&self.what)?;
-        if let Some(ref status) = self.channel.status {
-            f.write_str(" (")?;
-            f.write_str(&status)?;
-            f.write_str(")")?;
+fn last_stream_key(channel: &str) -> String {
+    format!("eris.announcements.stream_up.{}.last_stream", channel)
+}
+
+/// The last stream announced for a channel, used to skip duplicate announcements.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct LastStream {
+    stream_created_at: DateTime<FixedOffset>,
+    /// When the stream was last known to be live.
+    last_seen_at: DateTime<FixedOffset>,
+}
+
+impl LastStream {
+    /// Whether a stream that started at `stream_created_at` should be announced. Streams that
+    /// started within `grace_period` of the last one going offline are treated as the same stream.
+    fn is_new_stream(
+        last: Option<&LastStream>,
+        stream_created_at: DateTime<FixedOffset>,
+        grace_period: chrono::Duration,
+    ) -> bool {
+        match last {
+            Some(last) => {
+                stream_created_at > last.stream_created_at
+                    && stream_created_at - last.last_seen_at >= grace_period
+            }
+            None => true,
+        }
+    }
+
+    /// Whether a stream that's live at `now` should be announced, and what to remember about it
+    /// either way. Streams without a start time are taken to have just started.
+    fn update(
+        last: Option<&LastStream>,
+        stream_created_at: Option<DateTime<FixedOffset>>,
+        now: DateTime<FixedOffset>,
+        grace_period: chrono::Duration,
+    ) -> (bool, LastStream) {
+        let stream_created_at = stream_created_at.unwrap_or(now);
+        if LastStream::is_new_stream(last, stream_created_at, grace_period) {
+            (true, LastStream { stream_created_at, last_seen_at: now })
+        } else {
+            let stream_created_at = cmp::max(
+                stream_created_at,
+                last.map(|last| last.stream_created_at).unwrap_or(stream_created_at),
+            );
+            (false, LastStream { stream_created_at, last_seen_at: now })
         }
-        f.write_str("! <")?;
-        f.write_str(&self.channel.url)?;
-        f.write_str(">")?;
-        Ok(())
     }
 }
 
-async fn stream_up_inner(ctx: &ErisContext, channel: Channel) -> Result<(), Error> {
-    let data = ctx.data.read().await;
+/// The parts of the announcement that can change while the stream is live.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct StreamDetails {
+    show: String,
+    game: Option<String>,
+    title: Option<String>,
+    box_art_url: Option<String>,
+}
+
+/// A posted announcement. Kept in the `state` table so it can be edited after a restart.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Announcement {
+    channel_id: ChannelId,
+    message_id: MessageId,
+    name: String,
+    display_name: String,
+    url: String,
+    started_at: DateTime<FixedOffset>,
+    ended_at: Option<DateTime<FixedOffset>>,
+    details: StreamDetails,
+}
+
+impl Announcement {
+    fn fill_embed<'a>(&self, e: &'a mut CreateEmbed) -> &'a mut CreateEmbed {
+        if let Some(ended_at) = self.ended_at {
+            e.title(format!("{} was live", self.display_name)).colour(Colour::DARK_GREY).field(
+                "Duration",
+                HumanReadable::new(ended_at - self.started_at),
+                true,
+            );
+        } else {
+            e.title(format!("{} is live!", self.display_name))
+                .colour(Colour::from_rgb(100, 65, 165));
+        }
+
+        e.url(&self.url).field("Show", &self.details.show, true);
+        if let Some(ref game) = self.details.game {
+            e.field("Game", game, true);
+        }
+        e.field("Started", format!("<t:{}:R>", self.started_at.timestamp()), true);
+        if let Some(ref title) = self.details.title {
+            e.description(title);
+        }
+        if let Some(ref box_art_url) = self.details.box_art_url {
+            e.thumbnail(box_art_url);
+        }
+        e.timestamp(self.started_at.to_rfc3339())
+    }
+}
+
+async fn show_and_game(data: &TypeMap) -> Result<(Show, Option<String>), Error> {
     let lrrbot = data.extract::<LRRbot>()?;
-    let announcements_channel = data.extract::<Config>()?.announcements;
 
     let game_id = lrrbot.get_game_id().await.context("failed to get the game ID")?;
     let show_id = lrrbot.get_show_id().await.context("failed to get the show ID")?;
 
     let (game, show, game_entry) = {
-        let conn = ctx
-            .data
-            .read()
-            .await
+        let conn = data
             .extract::<PgPool>()?
             .get()
             .context("failed to get a database connection from the pool")?;
@@ -78,30 +163,221 @@ async fn stream_up_inner(ctx: &ErisContext, channel: Channel) -> Result<(), Erro
         (game, show, game_entry)
     };
 
-    let what = {
-        let game = game.as_ref();
-        let game_entry = game_entry.as_ref();
-        let game_display_name = game.map(|game| {
-            game_entry.and_then(|entry| entry.display_name.as_ref()).unwrap_or(&game.name)
-        });
+    let game =
+        game.map(|game| game_entry.and_then(|entry| entry.display_name).unwrap_or(game.name));
+
+    Ok((show, game))
+}
+
+/// Fetch the stream title and the game box art from Twitch.
+async fn twitch_details(
+    data: &TypeMap,
+    channel: &str,
+    fallback_game: Option<&str>,
+) -> Result<(Option<String>, Option<String>), Error> {
+    let token = {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+        User::by_name(&data.extract::<Config>()?.username, &conn)
+            .context("failed to load the bot user")?
+            .twitch_oauth
+            .context("token missing")?
+    };
+    let helix = data.extract::<Helix>()?;
+
+    let stream = helix
+        .get_streams(&token, &[UserId::Login(channel)])
+        .await
+        .context("failed to get the stream")?
+        .into_iter()
+        .next();
+
+    let game_id = match (stream.as_ref(), fallback_game) {
+        (Some(stream), _) => Some(GameId::Id(&stream.game_id)),
+        (None, Some(game)) => Some(GameId::Name(game)),
+        (None, None) => None,
+    };
+    let game = match game_id {
+        Some(game_id) => helix
+            .get_games(&token, &[game_id])
+            .await
+            .context("failed to get the game")?
+            .into_iter()
+            .next(),
+        None => None,
+    };
+
+    Ok((
+        stream.map(|stream| stream.title),
+        game.map(|game| game.box_art_url.replace("{width}x{height}", BOX_ART_SIZE)),
+    ))
+}
+
+async fn stream_details(
+    data: &TypeMap,
+    channel: &str,
+    fallback_title: Option<String>,
+    fallback_game: Option<&str>,
+) -> Result<(Show, StreamDetails), Error> {
+    let (show, game) = show_and_game(data).await?;
 
-        game_display_name.map(|name| format!("{} on {}", name, show.name)).unwrap_or(show.name)
+    let (title, box_art_url) = match twitch_details(data, channel, fallback_game).await {
+        Ok((title, box_art_url)) => (title.or(fallback_title), box_art_url),
+        Err(error) => {
+            error!(?error, "Failed to fetch the stream details from Twitch");
+            (fallback_title, None)
+        }
     };
 
-    announcements_channel
-        .say(ctx, format!("{}", StreamUp { channel, what }))
+    let details = StreamDetails { show: show.name.clone(), game, title, box_art_url };
+    Ok((show, details))
+}
+
+/// Mark the announcement for `channel` as live again after the stream came back.
+async fn reopen_announcement(
+    ctx: &ErisContext,
+    data: &TypeMap,
+    channel: &str,
+) -> Result<(), Error> {
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a database connection from the pool")?;
+
+    let mut announcement = match State::get::<Announcement, _>(STATE_KEY, &conn)
+        .context("failed to load the announcement message")?
+    {
+        Some(announcement) if announcement.ended_at.is_some() && announcement.name == channel => {
+            announcement
+        }
+        _ => return Ok(()),
+    };
+
+    announcement.ended_at = None;
+    announcement
+        .channel_id
+        .edit_message(ctx, announcement.message_id, |m| m.embed(|e| announcement.fill_embed(e)))
         .await
-        .context("failed to send the announcement message")?
-        .try_crosspost(ctx)
+        .context("failed to edit the announcement message")?;
+
+    State::set(STATE_KEY, &announcement, &conn)
+        .context("failed to save the announcement message")?;
+
+    Ok(())
+}
+
+async fn stream_up_inner(
+    ctx: &ErisContext,
+    channel: Channel,
+    now: DateTime<Utc>,
+) -> Result<(), Error> {
+    let now = now.with_timezone(&FixedOffset::east(0));
+    let data = ctx.data.read().await;
+    let config = data.extract::<Config>()?;
+    let announcements_channel = config.announcements;
+
+    let last_stream_key = last_stream_key(&channel.name);
+    let last_stream = {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+        State::get::<LastStream, _>(&last_stream_key, &conn)
+            .context("failed to load the last announced stream")?
+    };
+
+    let (is_new, last_stream) = LastStream::update(
+        last_stream.as_ref(),
+        channel.stream_created_at,
+        now,
+        config.stream_up_grace_period,
+    );
+    let stream_created_at = last_stream.stream_created_at;
+    if !is_new {
+        info!(
+            channel = channel.name.as_str(),
+            stream_created_at = ?channel.stream_created_at,
+            "Skipping the announcement of an already announced stream"
+        );
+
+        reopen_announcement(ctx, &data, &channel.name).await?;
+
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+        State::set(&last_stream_key, &last_stream, &conn)
+            .context("failed to save the last announced stream")?;
+
+        return Ok(());
+    }
+
+    let (show, details) =
+        stream_details(&data, &channel.name, channel.status.clone(), channel.game.as_deref())
+            .await?;
+    let roles = config
+        .show_roles
+        .get(&show.key.to_lowercase())
+        .into_iter()
+        .chain(config.stream_up_role.as_ref())
+        .copied()
+        .collect::<Vec<_>>();
+
+    let display_name = match channel.display_name {
+        Some(display_name) => display_name,
+        None => channel.name.clone(),
+    };
+    let mut announcement = Announcement {
+        channel_id: announcements_channel,
+        message_id: MessageId(0),
+        display_name,
+        name: channel.name,
+        url: channel.url,
+        started_at: stream_created_at,
+        ended_at: None,
+        details,
+    };
+
+    let message = announcements_channel
+        .send_message(ctx, |m| {
+            if !roles.is_empty() {
+                let mut content = MessageBuilder::new();
+                for (i, role) in roles.iter().enumerate() {
+                    if i != 0 {
+                        content.push(" ");
+                    }
+                    content.role(*role);
+                }
+                m.content(content.build());
+            }
+            m.allowed_mentions(|am| am.empty_parse().roles(roles.iter().copied()))
+                .embed(|e| announcement.fill_embed(e))
+        })
         .await
-        .context("failed to crosspost the announcement message")?;
+        .context("failed to send the announcement message")?;
+    announcement.message_id = message.id;
+
+    {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+        State::set(STATE_KEY, &announcement, &conn)
+            .context("failed to save the announcement message")?;
+        State::set(&last_stream_key, &last_stream, &conn)
+            .context("failed to save the last announced stream")?;
+    }
+
+    message.try_crosspost(ctx).await.context("failed to crosspost the announcement message")?;
 
     Ok(())
 }
 
 #[rpc_handler("announcements/stream_up")]
 pub async fn stream_up(ctx: ErisContext, data: Channel) -> Result<(), Error> {
-    let res = stream_up_inner(&ctx, data).await;
+    let res = stream_up_inner(&ctx, data, Utc::now()).await;
 
     if let Err(ref error) = res {
         error!(?error, "Failed to post a stream up announcement");
@@ -109,3 +385,207 @@ pub async fn stream_up(ctx: ErisContext, data: Channel) -> Result<(), Error> {
 
     res
 }
+
+async fn stream_down_inner(
+    ctx: &ErisContext,
+    channel: Channel,
+    now: DateTime<Utc>,
+) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a database connection from the pool")?;
+
+    let now = now.with_timezone(&FixedOffset::east(0));
+    let last_stream_key = last_stream_key(&channel.name);
+    if let Some(mut last_stream) = State::get::<LastStream, _>(&last_stream_key, &conn)
+        .context("failed to load the last announced stream")?
+    {
+        last_stream.last_seen_at = now;
+        State::set(&last_stream_key, last_stream, &conn)
+            .context("failed to save the last announced stream")?;
+    }
+
+    let mut announcement = match State::get::<Announcement, _>(STATE_KEY, &conn)
+        .context("failed to load the announcement message")?
+    {
+        Some(announcement)
+            if announcement.ended_at.is_none() && announcement.name == channel.name =>
+        {
+            announcement
+        }
+        _ => return Ok(()),
+    };
+
+    announcement.ended_at = Some(now);
+    announcement
+        .channel_id
+        .edit_message(ctx, announcement.message_id, |m| m.embed(|e| announcement.fill_embed(e)))
+        .await
+        .context("failed to edit the announcement message")?;
+
+    State::set(STATE_KEY, &announcement, &conn)
+        .context("failed to save the announcement message")?;
+
+    Ok(())
+}
+
+#[rpc_handler("announcements/stream_down")]
+pub async fn stream_down(ctx: ErisContext, data: Channel) -> Result<(), Error> {
+    let res = stream_down_inner(&ctx, data, Utc::now()).await;
+
+    if let Err(ref error) = res {
+        error!(?error, "Failed to mark the stream up announcement as ended");
+    }
+
+    res
+}
+
+async fn update_announcement(ctx: &ErisContext) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+
+    let mut announcement = {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+
+        match State::get::<Announcement, _>(STATE_KEY, &conn)
+            .context("failed to load the announcement message")?
+        {
+            Some(announcement) if announcement.ended_at.is_none() => announcement,
+            _ => return Ok(()),
+        }
+    };
+
+    let (_, details) = stream_details(
+        &data,
+        &announcement.name,
+        announcement.details.title.clone(),
+        announcement.details.game.as_deref(),
+    )
+    .await?;
+    if details == announcement.details {
+        return Ok(());
+    }
+    announcement.details = details;
+
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a database connection from the pool")?;
+    // `stream_down` might have marked the announcement as ended in the meantime.
+    match State::get::<Announcement, _>(STATE_KEY, &conn)
+        .context("failed to load the announcement message")?
+    {
+        Some(current)
+            if current.message_id == announcement.message_id && current.ended_at.is_none() => {}
+        _ => return Ok(()),
+    }
+
+    announcement
+        .channel_id
+        .edit_message(ctx, announcement.message_id, |m| m.embed(|e| announcement.fill_embed(e)))
+        .await
+        .context("failed to edit the announcement message")?;
+
+    State::set(STATE_KEY, &announcement, &conn)
+        .context("failed to save the announcement message")?;
+
+    Ok(())
+}
+
+/// Keep the game and the title in the stream up announcement up to date.
+pub async fn update_stream_up_announcement(ctx: ErisContext) {
+    let mut timer = tokio::time::interval(UPDATE_INTERVAL);
+
+    loop {
+        timer.tick().await;
+
+        if let Err(error) = update_announcement(&ctx).await {
+            error!(?error, "Failed to update the stream up announcement");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LastStream;
+    use chrono::{DateTime, Duration, FixedOffset};
+
+    fn at(time: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(time).unwrap()
+    }
+
+    #[test]
+    fn first_stream_is_announced() {
+        assert!(LastStream::is_new_stream(None, at("2021-11-12T18:00:00Z"), Duration::minutes(15)));
+    }
+
+    #[test]
+    fn same_stream_is_not_announced() {
+        let last = LastStream {
+            stream_created_at: at("2021-11-12T18:00:00Z"),
+            last_seen_at: at("2021-11-12T18:00:05Z"),
+        };
+        assert!(!LastStream::is_new_stream(
+            Some(&last),
+            at("2021-11-12T18:00:00Z"),
+            Duration::minutes(15)
+        ));
+        assert!(!LastStream::is_new_stream(
+            Some(&last),
+            at("2021-11-12T17:00:00Z"),
+            Duration::minutes(15)
+        ));
+    }
+
+    #[test]
+    fn grace_period() {
+        // The stream went offline at 22:00.
+        let last = LastStream {
+            stream_created_at: at("2021-11-12T18:00:00Z"),
+            last_seen_at: at("2021-11-12T22:00:00Z"),
+        };
+        assert!(!LastStream::is_new_stream(
+            Some(&last),
+            at("2021-11-12T22:14:59Z"),
+            Duration::minutes(15)
+        ));
+        assert!(LastStream::is_new_stream(
+            Some(&last),
+            at("2021-11-12T22:15:00Z"),
+            Duration::minutes(15)
+        ));
+        assert!(LastStream::is_new_stream(
+            Some(&last),
+            at("2021-11-13T18:00:00Z"),
+            Duration::minutes(15)
+        ));
+        assert!(LastStream::is_new_stream(
+            Some(&last),
+            at("2021-11-12T22:00:01Z"),
+            Duration::zero()
+        ));
+    }
+
+    #[test]
+    fn update() {
+        let now = at("2021-11-12T18:00:30Z");
+        let (is_new, first) = LastStream::update(None, None, now, Duration::minutes(15));
+        assert!(is_new);
+        assert_eq!(first, LastStream { stream_created_at: now, last_seen_at: now });
+
+        // Twitch reports the real start time later on.
+        let later = at("2021-11-12T18:05:00Z");
+        let (is_new, last) = LastStream::update(
+            Some(&first),
+            Some(at("2021-11-12T18:00:00Z")),
+            later,
+            Duration::minutes(15),
+        );
+        assert!(!is_new);
+        assert_eq!(last, LastStream { stream_created_at: now, last_seen_at: later });
+    }
+}
@@ -1,9 +1,9 @@
-use crate::config::Config;
+use crate::config::{Config, Replies, TweetRules};
 use crate::context::ErisContext;
 use crate::extract::Extract;
 use crate::models::State;
 use crate::try_crosspost::TryCrosspost;
-use crate::twitter::Twitter;
+use crate::twitter::{Tweet, Twitter};
 use crate::typemap_keys::PgPool;
 use anyhow::{Context, Error};
 use serenity::model::id::ChannelId;
@@ -34,11 +34,54 @@ async fn init(ctx: &ErisContext) -> Result<HashMap<u64, Vec<ChannelId>>, Error>
     Ok(users)
 }
 
+/// Whether `tweet` passes the account's `rules`. `users` are the watched accounts.
+fn should_post(rules: &TweetRules, tweet: &Tweet, users: &HashMap<u64, Vec<ChannelId>>) -> bool {
+    if tweet.retweeted_status.is_some() {
+        if !rules.retweets {
+            return false;
+        }
+    } else if !rules.mentions
+        && tweet.entities.user_mentions.iter().any(|mention| mention.indices.0 == 0)
+    {
+        return false;
+    }
+
+    let reply_allowed = match (tweet.in_reply_to_user_id, rules.replies) {
+        (None, _) | (Some(_), Replies::All) => true,
+        (Some(user_id), Replies::Watched) => users.contains_key(&user_id),
+        (Some(_), Replies::None) => false,
+    };
+    if !reply_allowed || (tweet.is_quote_status && !rules.quote_tweets) {
+        return false;
+    }
+
+    let text = match tweet.retweeted_status {
+        Some(ref retweet) => &retweet.full_text,
+        None => &tweet.full_text,
+    };
+    rules.include.as_ref().map(|include| include.is_match(text)).unwrap_or(true)
+        && !rules.exclude.as_ref().map(|exclude| exclude.is_match(text)).unwrap_or(false)
+}
+
+fn format_message(rules: &TweetRules, tweet: &Tweet) -> Result<String, Error> {
+    let mut vars = HashMap::new();
+    vars.insert(String::from("name"), tweet.user.name.clone());
+    vars.insert(String::from("screen_name"), tweet.user.screen_name.clone());
+    vars.insert(String::from("id"), tweet.id.to_string());
+    vars.insert(
+        String::from("url"),
+        format!("https://twitter.com/{}/status/{}", tweet.user.screen_name, tweet.id),
+    );
+    vars.insert(String::from("text"), tweet.full_text.clone());
+    strfmt::strfmt(&rules.template, &vars).context("failed to format the announcement message")
+}
+
 async fn inner<'a>(
     ctx: &'a ErisContext,
     users: &'a HashMap<u64, Vec<ChannelId>>,
 ) -> Result<(), Error> {
     let data = ctx.data.read().await;
+    let default_rules = TweetRules::default();
 
     for (&user_id, channels) in users {
         let state_key = &format!("eris.announcements.twitter.{}.last_tweet_id", user_id);
@@ -52,6 +95,7 @@ async fn inner<'a>(
         };
 
         let twitter = data.extract::<Twitter>()?;
+        let config = data.extract::<Config>()?;
 
         let mut tweets = twitter
             .user_timeline(user_id, true, true, 200, last_tweet_id)
@@ -62,22 +106,12 @@ async fn inner<'a>(
         if last_tweet_id.is_some() {
             tweets.sort_by_key(|tweet| tweet.id);
             for tweet in &tweets {
-                // (Non-reply tweet or a reply to an account we're watching) and (a retweet or doesn't start with a user mention)
-                if tweet
-                    .in_reply_to_user_id
-                    .map(|user_id| users.contains_key(&user_id))
-                    .unwrap_or(true)
-                    && (tweet.retweeted_status.is_some()
-                        || tweet
-                            .entities
-                            .user_mentions
-                            .iter()
-                            .all(|mention| mention.indices.0 != 0))
-                {
-                    let message = format!(
-                        "New tweet from {}: https://twitter.com/{}/status/{}",
-                        tweet.user.name, tweet.user.screen_name, tweet.id,
-                    );
+                let rules = config
+                    .twitter_rules
+                    .get(&tweet.user.screen_name.to_lowercase())
+                    .unwrap_or(&default_rules);
+                if should_post(rules, tweet, users) {
+                    let message = format_message(rules, tweet)?;
                     for channel in channels {
                         if let Some(retweeted_user_id) =
                             tweet.retweeted_status.as_ref().map(|tweet| tweet.user.id)
@@ -146,3 +180,86 @@ pub async fn post_tweets(ctx: ErisContext) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{format_message, should_post};
+    use crate::config::{Replies, TweetRules};
+    use crate::twitter::Tweet;
+    use regex::Regex;
+    use serenity::model::id::ChannelId;
+    use std::collections::HashMap;
+
+    fn posted(rules: &TweetRules) -> Vec<u64> {
+        let tweets =
+            serde_json::from_str::<Vec<Tweet>>(include_str!("../../fixtures/twitter/tweets.json"))
+                .unwrap();
+        let mut users = HashMap::new();
+        users.insert(10, vec![ChannelId(1)]);
+        users.insert(20, vec![ChannelId(2)]);
+
+        tweets
+            .iter()
+            .filter(|tweet| should_post(rules, tweet, &users))
+            .map(|tweet| tweet.id)
+            .collect()
+    }
+
+    #[test]
+    fn default_rules() {
+        assert_eq!(posted(&TweetRules::default()), vec![1, 3, 5, 6]);
+    }
+
+    #[test]
+    fn custom_rules() {
+        assert_eq!(
+            posted(&TweetRules { replies: Replies::All, mentions: true, ..TweetRules::default() }),
+            vec![1, 2, 3, 4, 5, 6]
+        );
+        assert_eq!(
+            posted(&TweetRules { replies: Replies::None, ..TweetRules::default() }),
+            vec![1, 5, 6]
+        );
+        assert_eq!(
+            posted(&TweetRules { retweets: false, quote_tweets: false, ..TweetRules::default() }),
+            vec![1, 3]
+        );
+        assert_eq!(
+            posted(&TweetRules {
+                include: Some(Regex::new("(?i)video|bus").unwrap()),
+                ..TweetRules::default()
+            }),
+            vec![1, 5]
+        );
+        assert_eq!(
+            posted(&TweetRules {
+                exclude: Some(Regex::new("(?i)video").unwrap()),
+                ..TweetRules::default()
+            }),
+            vec![3, 5, 6]
+        );
+    }
+
+    #[test]
+    fn message() {
+        let tweets =
+            serde_json::from_str::<Vec<Tweet>>(include_str!("../../fixtures/twitter/tweets.json"))
+                .unwrap();
+
+        assert_eq!(
+            format_message(&TweetRules::default(), &tweets[0]).unwrap(),
+            "New tweet from LoadingReadyRun: https://twitter.com/loadingreadyrun/status/1"
+        );
+        assert_eq!(
+            format_message(
+                &TweetRules {
+                    template: String::from("@{screen_name} says {text}"),
+                    ..TweetRules::default()
+                },
+                &tweets[0]
+            )
+            .unwrap(),
+            "@loadingreadyrun says New video: Road Quest is up!"
+        );
+    }
+}
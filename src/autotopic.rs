@@ -1,12 +1,13 @@
-use crate::config::Config;
+use crate::config::{Config, TopicConfig, TopicSource};
 use crate::context::ErisContext;
-use crate::desertbus::DesertBus;
+use crate::desertbus::{format_bussed, DesertBus, DesertBusSettings};
 use crate::extract::Extract;
-use crate::google::calendar::{Calendar, Event, LRR};
-use crate::models::{Game, GameEntry, Show, User};
+use crate::google::calendar::{Calendar, Event, FANSTREAMS, LRR};
+use crate::models::{Game, GameEntry, Show, State, User};
 use crate::rpc::client::HeaderInfo;
 use crate::rpc::LRRbot;
 use crate::shorten::shorten;
+use crate::twitch::fanstreams::LiveFanstreams;
 use crate::twitch::helix::UserId;
 use crate::twitch::Helix;
 use crate::typemap_keys::PgPool;
@@ -14,7 +15,12 @@ use anyhow::{Context, Error};
 use chrono::{DateTime, FixedOffset, Utc};
 use diesel::OptionalExtension;
 use separator::FixedPlaceSeparatable;
+use serde::{Deserialize, Serialize};
+use serenity::http::CacheHttp;
+use serenity::model::id::ChannelId;
 use serenity::prelude::TypeMap;
+use serenity::utils::MessageBuilder;
+use std::collections::HashMap;
 use std::fmt;
 use std::time::Duration;
 use tracing::error;
@@ -49,21 +55,134 @@ impl<'a> fmt::Display for EventDisplay<'a> {
     }
 }
 
+/// Fills in a topic template, trimming the whitespace left around empty variables.
+fn render(template: &str, vars: &[(&str, String)]) -> Result<String, Error> {
+    let vars =
+        vars.iter().map(|(name, value)| (String::from(*name), value)).collect::<HashMap<_, _>>();
+    let topic = strfmt::strfmt(template, &vars).context("failed to fill in the topic template")?;
+    Ok(String::from(topic.trim()))
+}
+
 pub async fn autotopic(ctx: ErisContext) {
+    let topics = match ctx.data.read().await.extract::<Config>() {
+        Ok(config) => config.topics.clone(),
+        Err(error) => {
+            error!(?error, "Failed to initialize autotopic");
+            return;
+        }
+    };
+    let mut autotopics =
+        topics.into_iter().map(|topic| Autotopic { topic, last_updated: None }).collect::<Vec<_>>();
+
     let mut timer = tokio::time::interval(Duration::from_secs(60));
-    let mut autotopic = Autotopic { last_updated: None };
 
     loop {
         timer.tick().await;
 
-        if let Err(error) = autotopic.update_topic(&ctx).await {
-            error!(?error, "Failed to update the topic");
+        for autotopic in &mut autotopics {
+            if let Err(error) = autotopic.update_topic(&ctx).await {
+                error!(?error, topic = autotopic.topic.name.as_str(), "Failed to update the topic");
+            }
+        }
+    }
+}
+
+/// What autotopic would set the channel's topic to now, or `None` if it would leave it alone or the
+/// channel's topic isn't managed.
+pub async fn preview(data: &TypeMap, channel: ChannelId) -> Result<Option<String>, Error> {
+    let topic = match data.extract::<Config>()?.topics.iter().find(|topic| topic.channel == channel)
+    {
+        Some(topic) => topic.clone(),
+        None => return Ok(None),
+    };
+
+    let autotopic = Autotopic { topic, last_updated: None };
+    Ok(autotopic
+        .generate(data)
+        .await?
+        .map(|(topic, _)| shorten(&topic, TOPIC_MAX_LEN).into_owned()))
+}
+
+/// A topic set by hand that autotopic leaves alone until it expires.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TopicOverride {
+    pub topic: String,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl TopicOverride {
+    fn state_key(channel: ChannelId) -> String {
+        format!("eris.autotopic.{}.override", channel.0)
+    }
+
+    pub fn get(data: &TypeMap, channel: ChannelId) -> Result<Option<TopicOverride>, Error> {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+
+        Ok(State::get::<Option<TopicOverride>, _>(&TopicOverride::state_key(channel), &conn)
+            .context("failed to load the topic override")?
+            .flatten())
+    }
+
+    pub fn set(&self, data: &TypeMap, channel: ChannelId) -> Result<(), Error> {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+
+        State::set(&TopicOverride::state_key(channel), self, &conn)
+            .context("failed to save the topic override")
+    }
+
+    pub fn clear(data: &TypeMap, channel: ChannelId) -> Result<(), Error> {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+
+        State::set(&TopicOverride::state_key(channel), None::<TopicOverride>, &conn)
+            .context("failed to clear the topic override")
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.until.map(|until| now < until).unwrap_or(true)
+    }
+
+    /// Sets the channel's topic to the override, unless it already is.
+    pub async fn apply(&self, cache_http: impl CacheHttp, channel: ChannelId) -> Result<(), Error> {
+        let topic = shorten(&self.topic, TOPIC_MAX_LEN);
+        let current = channel
+            .to_channel(&cache_http)
+            .await
+            .context("failed to get the channel")?
+            .guild()
+            .context("the channel is not a guild channel?")?;
+
+        if current.topic.as_deref() != Some(topic.as_ref()) {
+            channel
+                .edit(cache_http.http(), |c| c.topic(topic.as_ref()))
+                .await
+                .context("failed to set the topic override")?;
         }
+
+        Ok(())
     }
 }
 
-#[derive(Copy, Clone)]
+fn bot_user(data: &TypeMap) -> Result<User, Error> {
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a database connection from the pool")?;
+
+    User::by_name(&data.extract::<Config>()?.username, &conn).context("failed to load the bot user")
+}
+
+/// Manages the topic of a single channel.
 struct Autotopic {
+    topic: TopicConfig,
     last_updated: Option<DateTime<Utc>>,
 }
 
@@ -73,19 +192,18 @@ impl Autotopic {
         new_topic: &str,
         is_dynamic: bool,
         ctx: &ErisContext,
-        data: &TypeMap,
     ) -> Result<(), Error> {
         let new_topic = shorten(new_topic, TOPIC_MAX_LEN);
         let new_topic = new_topic.as_ref();
 
-        let mut channel = data
-            .extract::<Config>()?
-            .general_channel
+        let mut channel = self
+            .topic
+            .channel
             .to_channel(&ctx)
             .await
-            .context("failed to get the #general channel")?
+            .context("failed to get the channel")?
             .guild()
-            .context("#general is not a guild channel?")?;
+            .context("the channel is not a guild channel?")?;
 
         let old_topic = channel.topic.as_deref().unwrap_or_default();
 
@@ -124,6 +242,54 @@ impl Autotopic {
     async fn update_topic(&mut self, ctx: &ErisContext) -> Result<(), Error> {
         let data = ctx.data.read().await;
 
+        if let Some(topic_override) = TopicOverride::get(&data, self.topic.channel)? {
+            if topic_override.is_active(Utc::now()) {
+                return topic_override.apply(ctx, self.topic.channel).await;
+            }
+            TopicOverride::clear(&data, self.topic.channel)?;
+        }
+
+        if let Some((topic, is_dynamic)) = self.generate(&data).await? {
+            self.set_topic(&topic, is_dynamic, ctx).await.context("failed to update the topic")?;
+        }
+
+        Ok(())
+    }
+
+    /// The topic and whether it's dynamic, or `None` if the topic should be left alone.
+    async fn generate(&self, data: &TypeMap) -> Result<Option<(String, bool)>, Error> {
+        match self.topic.source {
+            TopicSource::Lrr => Ok(Some(self.lrr_topic(data).await?)),
+            TopicSource::Fanstreams => Ok(Some(self.fanstreams_topic(data).await?)),
+            TopicSource::DesertBus => self.desertbus(data, &bot_user(data)?, Utc::now(), &[]).await,
+        }
+    }
+
+    async fn fanstreams_topic(&self, data: &TypeMap) -> Result<(String, bool), Error> {
+        let templates = &self.topic.templates;
+        let fanstreams = LiveFanstreams::fetch(data).await?;
+
+        if fanstreams.streams.is_empty() {
+            let now = Utc::now();
+            let events = data
+                .extract::<Calendar>()?
+                .get_upcoming_events(FANSTREAMS, now)
+                .await
+                .context("failed to get the next scheduled fan stream")?;
+            let next_events = Calendar::get_next_event(&events, now, true)
+                .iter()
+                .map(|event| EventDisplay { event }.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            Ok((render(&templates.fanstreams_offline, &[("next_events", next_events)])?, false))
+        } else {
+            let mut streams = MessageBuilder::new();
+            fanstreams.push_streams(&mut streams);
+            Ok((render(&templates.fanstreams_live, &[("streams", streams.build())])?, true))
+        }
+    }
+
+    async fn lrr_topic(&self, data: &TypeMap) -> Result<(String, bool), Error> {
         let header = match data.extract::<LRRbot>()?.get_header_info().await {
             Ok(header) => header,
             Err(error) => {
@@ -139,10 +305,10 @@ impl Autotopic {
             }
         };
 
-        let mut messages = vec![];
+        let templates = &self.topic.templates;
         let mut is_dynamic = false;
 
-        let user;
+        let user = bot_user(data)?;
         let game;
         let show;
         let game_entry;
@@ -153,9 +319,6 @@ impl Autotopic {
                 .get()
                 .context("failed to get a database connection from the pool")?;
 
-            user = User::by_name(&data.extract::<Config>()?.username, &conn)
-                .context("failed to load the bot user")?;
-
             if header.is_live {
                 game = header
                     .current_game
@@ -182,30 +345,44 @@ impl Autotopic {
             }
         }
 
-        if header.is_live {
-            match (game, show) {
-                (Some(game), Some(show)) => {
-                    messages.push(format!(
-                        "Now live: {} on {}.",
-                        game_entry.and_then(|entry| entry.display_name).unwrap_or(game.name),
-                        show.name
-                    ));
-                }
-                (Some(game), None) => {
-                    messages.push(format!(
-                        "Now live: {}.",
-                        game_entry.and_then(|entry| entry.display_name).unwrap_or(game.name)
-                    ));
+        let mut topic = if header.is_live {
+            let (started, uptime) = match self.stream_started_at(data, &user, &header.channel).await
+            {
+                Ok(Some(started_at)) => {
+                    let started = format!("<t:{}:R>", started_at.timestamp());
+                    let uptime = format!("The stream started {}.", started);
+                    (started, uptime)
                 }
-                (None, Some(show)) => {
-                    messages.push(format!("Now live: {}.", show.name));
+                Ok(None) => (String::new(), String::from("The stream is not live.")),
+                Err(error) => {
+                    error!(?error, "failed to fetch the stream's start time");
+                    (String::new(), String::new())
                 }
-                (None, None) => messages.push(String::from("Now live: something?")),
-            }
+            };
+
+            let game = game
+                .map(|game| game_entry.and_then(|entry| entry.display_name).unwrap_or(game.name));
+            let show = show.map(|show| show.name);
+            let title = match (&game, &show) {
+                (Some(game), Some(show)) => Some(format!("{} on {}", game, show)),
+                (Some(title), None) | (None, Some(title)) => Some(title.clone()),
+                (None, None) => None,
+            };
 
-            match self.uptime_msg(&data, &user, &header.channel).await {
-                Ok(msg) => messages.push(msg),
-                Err(error) => error!(?error, "failed to generate the uptime message"),
+            match title {
+                Some(title) => render(
+                    &templates.live,
+                    &[
+                        ("game", game.unwrap_or_default()),
+                        ("show", show.unwrap_or_default()),
+                        ("title", title),
+                        ("started", started),
+                        ("uptime", uptime),
+                    ],
+                )?,
+                None => {
+                    render(&templates.live_unknown, &[("started", started), ("uptime", uptime)])?
+                }
             }
         } else {
             let now = Utc::now();
@@ -217,32 +394,38 @@ impl Autotopic {
                 .context("failed to get the next scheduled stream")?;
             let events = Calendar::get_next_event(&events, now, false);
 
-            let (desertbus, desertbus_is_dynamic) =
-                self.desertbus(&data, &user, now, &events).await?;
-            if !desertbus.is_empty() {
-                messages.extend(desertbus);
-                is_dynamic |= desertbus_is_dynamic;
-            } else {
-                messages.extend(events.iter().map(|event| EventDisplay { event }.to_string()));
+            match self.desertbus(data, &user, now, events).await? {
+                Some((desertbus, desertbus_is_dynamic)) => {
+                    is_dynamic |= desertbus_is_dynamic;
+                    desertbus
+                }
+                None => {
+                    let next_events = events
+                        .iter()
+                        .map(|event| EventDisplay { event }.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    render(&templates.offline, &[("next_events", next_events)])?
+                }
             }
-        }
+        };
 
-        let mut topic = messages.join(" ");
         if let Some(advice) = header.advice {
             if !topic.is_empty() {
                 topic.push_str(DYNAMIC_TAIL_SEPARATOR);
             }
-            topic.push_str(&advice);
+            topic.push_str(&render(&templates.advice, &[("advice", advice)])?);
         }
 
-        self.set_topic(&topic, is_dynamic, ctx, &data)
-            .await
-            .context("failed to update the topic")?;
-
-        Ok(())
+        Ok((topic, is_dynamic))
     }
 
-    async fn uptime_msg(self, data: &TypeMap, user: &User, channel: &str) -> Result<String, Error> {
+    async fn stream_started_at(
+        &self,
+        data: &TypeMap,
+        user: &User,
+        channel: &str,
+    ) -> Result<Option<DateTime<FixedOffset>>, Error> {
         Ok(data
             .extract::<Helix>()?
             .get_streams(
@@ -252,27 +435,24 @@ impl Autotopic {
             .await
             .context("failed to get the stream")?
             .first()
-            .map(|stream| format!("The stream started <t:{}:R>.", stream.started_at.timestamp()))
-            .unwrap_or_else(|| String::from("The stream is not live.")))
+            .map(|stream| stream.started_at))
     }
 
     async fn desertbus(
-        self,
+        &self,
         data: &TypeMap,
         user: &User,
         now: DateTime<Utc>,
         events: &[Event],
-    ) -> Result<(Vec<String>, bool), Error> {
-        let start = DesertBus::start_time().with_timezone(&Utc);
-        let announce_start = start - chrono::Duration::days(2);
-        let announce_end = start + chrono::Duration::days(9);
-        let mut messages = vec![];
-        let mut is_dynamic = false;
+    ) -> Result<Option<(String, bool)>, Error> {
+        let templates = &self.topic.templates;
+        let settings = DesertBusSettings::load(data)?;
+        let start = settings.start_time();
 
-        if announce_start <= now && now <= announce_end {
+        if settings.in_announce_window(now) {
             if let Some(next_event_start) = events.get(0).map(|event| event.start) {
                 if next_event_start.with_timezone(&Utc) < start {
-                    return Ok((messages, is_dynamic));
+                    return Ok(None);
                 }
             }
             let desertbus = data.extract::<DesertBus>()?;
@@ -281,57 +461,51 @@ impl Autotopic {
                 Ok(money_raised) => money_raised,
                 Err(error) => {
                     error!(?error, "Failed to fetch the current Desert Bus total");
-                    messages.push(String::from("DESERT BUS?"));
-                    return Ok((messages, is_dynamic));
+                    return Ok(Some((render(&templates.desertbus_unknown, &[])?, false)));
                 }
             };
-            let total_hours = DesertBus::hours_raised(money_raised) as i64;
-            if now < start {
-                messages.push(
-                    EventDisplay {
-                        event: &Event {
-                            start: start.with_timezone(&FixedOffset::east(0)),
-                            summary: String::from("Desert Bus for Hope"),
-                            end: start.with_timezone(&FixedOffset::east(0))
-                                + chrono::Duration::hours(total_hours),
-                            location: Some(String::from(
-                                "https://desertbus.org/ or https://twitch.tv/desertbus",
-                            )),
-                            description: None,
-                        },
-                    }
-                    .to_string(),
-                );
-                messages.push(format!(
-                    "${} raised.",
-                    money_raised.separated_string_with_fixed_place(2)
-                ));
-                is_dynamic = true;
-            } else if now <= start + chrono::Duration::hours(total_hours)
-                || self.is_desertbus_live(data, user).await?
-            {
-                messages.push(String::from(
-                    "DESERT BUS! (https://desertbus.org/ or https://twitch.tv/desertbus)",
-                ));
-                messages.push(format!(
-                    "${} raised.",
-                    money_raised.separated_string_with_fixed_place(2)
-                ));
-                let bussed = now - start;
-                messages.push(format!(
-                    "{}:{:02} hours of {} so far.",
-                    bussed.num_hours(),
-                    bussed.num_minutes() % 60,
-                    total_hours
-                ));
-                is_dynamic = true;
+            let progress = settings.progress(money_raised, now);
+            let total = money_raised.separated_string_with_fixed_place(2);
+            if !progress.has_started() {
+                let next_event = EventDisplay {
+                    event: &Event {
+                        id: String::from("desertbus"),
+                        start: start.with_timezone(&FixedOffset::east(0)),
+                        summary: String::from("Desert Bus for Hope"),
+                        end: progress.end().with_timezone(&FixedOffset::east(0)),
+                        location: Some(String::from(
+                            "https://desertbus.org/ or https://twitch.tv/desertbus",
+                        )),
+                        description: None,
+                    },
+                }
+                .to_string();
+                let topic = render(
+                    &templates.desertbus_upcoming,
+                    &[
+                        ("next_events", next_event),
+                        ("total", total),
+                        ("hours", progress.hours.to_string()),
+                    ],
+                )?;
+                return Ok(Some((topic, true)));
+            } else if !progress.is_over() || self.is_desertbus_live(data, user).await? {
+                let topic = render(
+                    &templates.desertbus_live,
+                    &[
+                        ("total", total),
+                        ("hours", progress.hours.to_string()),
+                        ("bussed", format_bussed(progress.bussed())),
+                    ],
+                )?;
+                return Ok(Some((topic, true)));
             }
         }
 
-        Ok((messages, is_dynamic))
+        Ok(None)
     }
 
-    async fn is_desertbus_live(self, data: &TypeMap, user: &User) -> Result<bool, Error> {
+    async fn is_desertbus_live(&self, data: &TypeMap, user: &User) -> Result<bool, Error> {
         if let Some(token) = user.twitch_oauth.as_ref() {
             Ok(!data
                 .extract::<Helix>()?
@@ -345,3 +519,63 @@ impl Autotopic {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{render, TopicOverride};
+    use crate::config::AutotopicTemplates;
+    use chrono::{DateTime, Utc};
+
+    #[test]
+    fn default_templates() {
+        let templates = AutotopicTemplates::default();
+
+        assert_eq!(
+            render(
+                &templates.live,
+                &[
+                    ("game", String::from("Hades")),
+                    ("show", String::from("Let's Play")),
+                    ("title", String::from("Hades on Let's Play")),
+                    ("started", String::from("<t:1640030400:R>")),
+                    ("uptime", String::from("The stream started <t:1640030400:R>.")),
+                ]
+            )
+            .unwrap(),
+            "Now live: Hades on Let's Play. The stream started <t:1640030400:R>."
+        );
+        assert_eq!(
+            render(
+                &templates.live_unknown,
+                &[("started", String::new()), ("uptime", String::new())]
+            )
+            .unwrap(),
+            "Now live: something?"
+        );
+        assert_eq!(
+            render(&templates.desertbus_live, &[
+                ("total", String::from("1,234.56")),
+                ("hours", String::from("105")),
+                ("bussed", String::from("12:05")),
+            ])
+            .unwrap(),
+            "DESERT BUS! (https://desertbus.org/ or https://twitch.tv/desertbus) $1,234.56 raised. 12:05 hours of 105 so far."
+        );
+        assert!(render("{nope}", &[]).is_err());
+    }
+
+    #[test]
+    fn override_expiry() {
+        let at = |s| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let until = TopicOverride {
+            topic: String::from("Party!"),
+            until: Some(at("2022-01-15T20:00:00Z")),
+        };
+        assert!(until.is_active(at("2022-01-15T19:59:59Z")));
+        assert!(!until.is_active(at("2022-01-15T20:00:00Z")));
+        assert!(!until.is_active(at("2022-01-16T00:00:00Z")));
+
+        let forever = TopicOverride { topic: String::from("Party!"), until: None };
+        assert!(forever.is_active(at("2100-01-01T00:00:00Z")));
+    }
+}
@@ -0,0 +1,406 @@
+use crate::config::Config;
+use crate::context::ErisContext;
+use crate::extract::Extract;
+use crate::models::State;
+use crate::typemap_keys::PgPool;
+use anyhow::{Context, Error};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use serenity::http::client::Http;
+use serenity::http::AttachmentType;
+use serenity::http::CacheHttp;
+use serenity::model::prelude::*;
+use serenity::prelude::TypeMap;
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::time::Duration;
+use tracing::{error, info};
+
+const STATE_KEY: &str = "eris.channel_expiry.channels";
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+/// How many times to try exporting the history before expiring the channel without it.
+const MAX_EXPORT_ATTEMPTS: u32 = 5;
+/// Only the latest messages are exported from very busy channels.
+const MAX_EXPORT_MESSAGES: usize = 10_000;
+/// Discord's upload limit is 8 MiB, leave some room for the rest of the request.
+const MAX_EXPORT_FILE_SIZE: usize = 8_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Kind {
+    /// Deleted when it expires.
+    Channel,
+    /// Archived and locked when it expires.
+    Thread,
+}
+
+/// A text channel or thread created with `!tempchannel` or `!tempthread`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExpiringChannel {
+    pub id: ChannelId,
+    pub name: String,
+    pub kind: Kind,
+    pub expires_at: DateTime<Utc>,
+    /// Whether to send the history to the mods channel before it goes away.
+    pub export: bool,
+    /// Whether the history has been sent already, so that it's not sent again if deleting fails.
+    #[serde(default)]
+    pub exported: bool,
+    #[serde(default)]
+    pub export_attempts: u32,
+}
+
+impl ExpiringChannel {
+    fn load(data: &TypeMap) -> Result<Vec<ExpiringChannel>, Error> {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+        Ok(State::get(STATE_KEY, &conn)
+            .context("failed to load the expiring channels")?
+            .unwrap_or_default())
+    }
+
+    /// Changes the list of expiring channels, without losing changes made at the same time.
+    fn modify<F: FnOnce(&mut Vec<ExpiringChannel>)>(data: &TypeMap, f: F) -> Result<(), Error> {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+        State::update(STATE_KEY, &conn, f).context("failed to save the expiring channels")
+    }
+
+    pub fn add(&self, data: &TypeMap) -> Result<(), Error> {
+        ExpiringChannel::modify(data, |channels| channels.push(self.clone()))
+    }
+
+    fn update(&self, data: &TypeMap) -> Result<(), Error> {
+        ExpiringChannel::modify(data, |channels| {
+            for channel in channels {
+                if channel.id == self.id {
+                    *channel = self.clone();
+                }
+            }
+        })
+    }
+
+    fn wants_export(&self) -> bool {
+        self.export && !self.exported && self.export_attempts < MAX_EXPORT_ATTEMPTS
+    }
+}
+
+fn expired(channels: Vec<ExpiringChannel>, now: DateTime<Utc>) -> Vec<ExpiringChannel> {
+    channels.into_iter().filter(|channel| channel.expires_at <= now).collect()
+}
+
+/// The notice posted in a new expiring channel.
+pub fn notice(channel: &ExpiringChannel) -> String {
+    let what = match channel.kind {
+        Kind::Channel => "channel is temporary and will be deleted",
+        Kind::Thread => "thread is temporary and will be archived",
+    };
+    let mut notice = format!(
+        "This {} <t:{timestamp}:R> (<t:{timestamp}:F>).",
+        what,
+        timestamp = channel.expires_at.timestamp()
+    );
+    if channel.export {
+        notice.push_str(" The mods will keep a copy of the messages.");
+    }
+    notice
+}
+
+fn format_history(messages: &[Message]) -> String {
+    let mut history = String::new();
+    for message in messages {
+        let _ = write!(
+            history,
+            "[{}] {}#{:04}: {}",
+            message.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            message.author.name,
+            message.author.discriminator,
+            message.content
+        );
+        for attachment in &message.attachments {
+            let _ = write!(history, " {}", attachment.url);
+        }
+        history.push('\n');
+    }
+    history
+}
+
+/// Splits `history` into parts of at most `max_len` bytes, between lines where possible.
+fn split_history(history: &str, max_len: usize) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut rest = history;
+    while rest.len() > max_len {
+        let mut limit = max_len;
+        while !rest.is_char_boundary(limit) {
+            limit -= 1;
+        }
+        let end = match rest[..limit].rfind('\n') {
+            Some(newline) => newline + 1,
+            None => limit,
+        };
+        let (part, remainder) = rest.split_at(end);
+        parts.push(part);
+        rest = remainder;
+    }
+    if !rest.is_empty() {
+        parts.push(rest);
+    }
+    parts
+}
+
+/// Sends what was said in `channel` to the mods channel as text files.
+async fn export_history(
+    http: &Http,
+    mods_channel: ChannelId,
+    channel: &ExpiringChannel,
+) -> Result<(), Error> {
+    // Fetch past the limit to tell whether there's more.
+    let mut messages = vec![];
+    while messages.len() <= MAX_EXPORT_MESSAGES {
+        let before = messages.last().map(|message: &Message| message.id);
+        let page = channel
+            .id
+            .messages(http, |m| match before {
+                Some(before) => m.before(before).limit(100),
+                None => m.limit(100),
+            })
+            .await
+            .context("failed to fetch the channel history")?;
+        if page.is_empty() {
+            break;
+        }
+        messages.extend(page);
+    }
+    let truncated = messages.len() > MAX_EXPORT_MESSAGES;
+    messages.truncate(MAX_EXPORT_MESSAGES);
+    messages.reverse();
+
+    let history = format_history(&messages);
+    let parts = split_history(&history, MAX_EXPORT_FILE_SIZE);
+    let count = parts.len();
+    for (i, part) in parts.into_iter().enumerate() {
+        mods_channel
+            .send_message(http, |m| {
+                if i == 0 {
+                    let mut content =
+                        format!("History of the temporary {:?} before it expired:", channel.name);
+                    if truncated {
+                        let _ =
+                            write!(content, " (only the last {} messages)", MAX_EXPORT_MESSAGES);
+                    }
+                    m.content(content);
+                }
+                let filename = if count == 1 {
+                    format!("{}.txt", channel.id)
+                } else {
+                    format!("{}-{}.txt", channel.id, i + 1)
+                };
+                m.add_file(AttachmentType::Bytes {
+                    data: Cow::Owned(part.as_bytes().to_vec()),
+                    filename,
+                })
+            })
+            .await
+            .context("failed to send the channel history")?;
+    }
+
+    Ok(())
+}
+
+async fn expire(http: &Http, channel: &ExpiringChannel) -> Result<(), Error> {
+    match channel.kind {
+        Kind::Channel => {
+            channel.id.delete(http).await.context("failed to delete the channel")?;
+        }
+        Kind::Thread => {
+            // Serenity doesn't have a builder for editing threads.
+            let mut map = Map::new();
+            map.insert(String::from("archived"), Value::Bool(true));
+            map.insert(String::from("locked"), Value::Bool(true));
+            http.edit_channel(channel.id.0, &map).await.context("failed to archive the thread")?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn expire_channels(ctx: &ErisContext) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+    let config = data.extract::<Config>()?;
+
+    let expired = expired(ExpiringChannel::load(&data)?, Utc::now());
+    if expired.is_empty() {
+        return Ok(());
+    }
+
+    let mut done = vec![];
+    for mut channel in expired {
+        info!(
+            channel.id = channel.id.0,
+            channel.name = channel.name.as_str(),
+            "Expiring a channel"
+        );
+
+        if channel.wants_export() {
+            match export_history(ctx.http(), config.mods_channel, &channel).await {
+                Ok(()) => channel.exported = true,
+                Err(error) => {
+                    channel.export_attempts += 1;
+                    error!(
+                        ?error,
+                        channel.id = channel.id.0,
+                        attempts = channel.export_attempts,
+                        "Failed to export the history of a channel"
+                    );
+                    if !channel.wants_export() {
+                        let notice = format!(
+                            "Couldn't export the history of the temporary {:?} after {} attempts, so it expires without a copy.",
+                            channel.name, MAX_EXPORT_ATTEMPTS
+                        );
+                        let sent = config
+                            .mods_channel
+                            .send_message(ctx.http(), |m| {
+                                m.content(notice).allowed_mentions(|am| am.empty_parse())
+                            })
+                            .await;
+                        if let Err(error) = sent {
+                            error!(?error, "Failed to tell the mods that an export failed");
+                        }
+                    }
+                }
+            }
+            channel.update(&data)?;
+            if channel.wants_export() {
+                continue;
+            }
+        }
+
+        if let Err(error) = expire(ctx.http(), &channel).await {
+            error!(?error, channel.id = channel.id.0, "Failed to expire a channel");
+            // Try again later, unless it's gone already.
+            if channel.id.to_channel(&ctx).await.is_ok() {
+                continue;
+            }
+        }
+        done.push(channel.id);
+    }
+
+    // Channels might have been added in the meantime.
+    ExpiringChannel::modify(&data, |channels| {
+        channels.retain(|channel| !done.contains(&channel.id))
+    })
+}
+
+pub async fn channel_expiry(ctx: ErisContext) {
+    let mut timer = tokio::time::interval(EXPIRY_INTERVAL);
+
+    loop {
+        timer.tick().await;
+
+        if let Err(error) = expire_channels(&ctx).await {
+            error!(?error, "Failed to expire channels");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expired, format_history, notice, split_history, ExpiringChannel, Kind};
+    use chrono::{DateTime, Utc};
+    use serde_json::json;
+    use serenity::model::prelude::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn channel(id: u64, kind: Kind, expires_at: &str, export: bool) -> ExpiringChannel {
+        ExpiringChannel {
+            id: ChannelId(id),
+            name: String::from("game-night"),
+            kind,
+            expires_at: at(expires_at),
+            export,
+            exported: false,
+            export_attempts: 0,
+        }
+    }
+
+    #[test]
+    fn notices() {
+        assert_eq!(
+            notice(&channel(1, Kind::Channel, "2022-01-15T20:00:00Z", false)),
+            "This channel is temporary and will be deleted <t:1642276800:R> (<t:1642276800:F>)."
+        );
+        assert_eq!(
+            notice(&channel(1, Kind::Thread, "2022-01-15T20:00:00Z", true)),
+            "This thread is temporary and will be archived <t:1642276800:R> (<t:1642276800:F>). The mods will keep a copy of the messages."
+        );
+    }
+
+    #[test]
+    fn expiry() {
+        let channels = vec![
+            channel(1, Kind::Channel, "2022-01-15T20:00:00Z", false),
+            channel(2, Kind::Thread, "2022-01-15T21:00:00Z", false),
+            channel(3, Kind::Channel, "2022-01-15T19:00:00Z", true),
+        ];
+        let ids = expired(channels, at("2022-01-15T20:00:00Z"))
+            .into_iter()
+            .map(|channel| channel.id.0)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 3]);
+    }
+
+    #[test]
+    fn history() {
+        let message = |content: &str, attachments| {
+            serde_json::from_value::<Message>(json!({
+                "id": "2",
+                "channel_id": "1",
+                "author": { "id": "3", "username": "Ian", "discriminator": "0042", "avatar": null },
+                "content": content,
+                "timestamp": "2022-01-15T20:01:02Z",
+                "edited_timestamp": null,
+                "tts": false,
+                "mention_everyone": false,
+                "mentions": [],
+                "mention_roles": [],
+                "attachments": attachments,
+                "embeds": [],
+                "pinned": false,
+                "type": 0,
+            }))
+            .unwrap()
+        };
+        let messages = vec![
+            message("hello", json!([])),
+            message(
+                "look",
+                json!([{
+                    "id": "4",
+                    "filename": "a.png",
+                    "size": 1,
+                    "url": "https://cdn.example/a.png",
+                    "proxy_url": "https://cdn.example/a.png",
+                }]),
+            ),
+        ];
+        assert_eq!(
+            format_history(&messages),
+            "[2022-01-15 20:01:02] Ian#0042: hello\n[2022-01-15 20:01:02] Ian#0042: look https://cdn.example/a.png\n"
+        );
+    }
+
+    #[test]
+    fn splitting() {
+        assert_eq!(split_history("", 10), Vec::<&str>::new());
+        assert_eq!(split_history("one\ntwo\nthree\n", 9), vec!["one\ntwo\n", "three\n"]);
+        assert_eq!(split_history("abcdef", 4), vec!["abcd", "ef"]);
+        assert_eq!(split_history("ääää", 3), vec!["ä", "ä", "ä", "ä"]);
+    }
+}
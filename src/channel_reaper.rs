@@ -1,7 +1,9 @@
 use crate::config::Config;
 use crate::context::ErisContext;
 use crate::extract::Extract;
-use anyhow::Error;
+use crate::models::TempChannel;
+use crate::typemap_keys::PgPool;
+use anyhow::{Context, Error};
 use chrono::Utc;
 use serenity::model::prelude::*;
 use std::collections::HashMap;
@@ -10,7 +12,6 @@ use tracing::{error, info};
 
 const STARTUP_DELAY: Duration = Duration::from_secs(5);
 const REAP_INTERVAL: Duration = Duration::from_secs(60);
-const MIN_CHANNEL_AGE: Duration = Duration::from_secs(15 * 60);
 
 async fn reap_channels(ctx: &ErisContext) -> Result<(), Error> {
     let data = ctx.data.read().await;
@@ -28,22 +29,52 @@ async fn reap_channels(ctx: &ErisContext) -> Result<(), Error> {
         }
     }
 
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a DB connection from the connection pool")?;
+    let tracked = TempChannel::all(&conn).context("failed to get the temporary channels")?;
+
+    // Forget about channels that have been deleted some other way.
+    for temp_channel in &tracked {
+        if !guild.channels.contains_key(&ChannelId(temp_channel.id as u64)) {
+            TempChannel::delete(temp_channel.id, &conn)
+                .context("failed to forget a deleted temporary channel")?;
+        }
+    }
+
     let now = Utc::now();
 
     let mut unused_channels = vec![];
 
     for channel in guild.channels.values() {
-        if channel.kind != ChannelType::Voice
-            || !channel.name.starts_with(&config.temp_channel_prefix)
-        {
+        if channel.kind != ChannelType::Voice {
+            continue;
+        }
+
+        let last_occupied_at = match tracked.iter().find(|temp| temp.id == channel.id.0 as i64) {
+            Some(temp_channel) => temp_channel.last_occupied_at,
+            // Channels from before they were tracked.
+            None if channel.name.starts_with(&config.temp_channel_prefix) => {
+                channel.id.created_at().with_timezone(&Utc)
+            }
+            None => continue,
+        };
+
+        if voice_users.get(&channel.id).copied().unwrap_or(0) > 0 {
+            // In case a voice state update was missed.
+            TempChannel::touch(channel.id.0 as i64, now, &conn)
+                .context("failed to update when a temporary channel was last occupied")?;
             continue;
         }
 
-        let created_at = channel.id.created_at().with_timezone(&Utc);
+        let timeout = channel
+            .category_id
+            .and_then(|category| config.temp_channel_category_timeouts.get(&category))
+            .copied()
+            .unwrap_or(config.temp_channel_idle_timeout);
 
-        if (now - created_at).to_std()? > MIN_CHANNEL_AGE
-            && voice_users.get(&channel.id).copied().unwrap_or(0) == 0
-        {
+        if now - last_occupied_at > timeout {
             info!(
                 channel.id = channel.id.0,
                 channel.name = channel.name.as_str(),
@@ -56,6 +87,9 @@ async fn reap_channels(ctx: &ErisContext) -> Result<(), Error> {
     for channel_id in unused_channels {
         if let Err(error) = channel_id.delete(&ctx).await {
             error!(?error, channel.id = channel_id.0, "Failed to delete a temporary channel");
+        } else {
+            TempChannel::delete(channel_id.0 as i64, &conn)
+                .context("failed to forget a deleted temporary channel")?;
         }
     }
 
@@ -0,0 +1,170 @@
+use crate::config::Config;
+use crate::contact::{matches_username, ContactThread, Status};
+use crate::extract::Extract;
+use serenity::framework::standard::macros::{command, group};
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tracing::error;
+
+#[group("Contact form")]
+#[description = "Commands for answering messages from the contact form"]
+#[prefix = "contact"]
+#[only_in(guilds)]
+#[commands(reply)]
+struct Contact;
+
+/// Splits off the first word.
+fn first_word(args: &str) -> (&str, &str) {
+    let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    (first, rest.trim())
+}
+
+/// Splits `[open|answered|closed] [dm] MESSAGE`.
+fn parse_reply(args: &str) -> (Option<Status>, bool, &str) {
+    let args = args.trim();
+    let (first, rest) = first_word(args);
+    let (status, args) = match first.parse() {
+        Ok(status) => (Some(status), rest),
+        Err(_) => (None, args),
+    };
+    match first_word(args) {
+        (first, rest) if first.eq_ignore_ascii_case("dm") => (status, true, rest),
+        _ => (status, false, args),
+    }
+}
+
+/// Finds the guild member the sender of a contact form message said they are.
+async fn find_member(ctx: &Context, guild: GuildId, username: &str) -> Option<User> {
+    let query = username.trim().trim_start_matches('@');
+    let query = query.rsplit_once('#').map(|(name, _)| name).unwrap_or(query);
+    let members = match guild.search_members(ctx, query, Some(100)).await {
+        Ok(members) => members,
+        Err(error) => {
+            error!(?error, "Failed to search for the sender of a contact form message");
+            return None;
+        }
+    };
+    let mut matches = members.into_iter().filter(|member| matches_username(&member.user, username));
+    // Several people can have the same name without a discriminator, so don't guess.
+    match (matches.next(), matches.next()) {
+        (Some(member), None) => Some(member.user),
+        _ => None,
+    }
+}
+
+/// Whether the author of `msg` is a mod, someone who can manage messages in the mods channel.
+async fn is_mod(ctx: &Context, msg: &Message, mods_channel: ChannelId) -> bool {
+    let (guild, member) = match (msg.guild(ctx).await, msg.member(ctx).await) {
+        (Some(guild), Ok(member)) => (guild, member),
+        _ => return false,
+    };
+    match guild.channels.get(&mods_channel) {
+        Some(channel) => guild
+            .user_permissions_in(channel, &member)
+            .map(|permissions| permissions.manage_messages())
+            .unwrap_or(false),
+        None => false,
+    }
+}
+
+#[command]
+#[description = "Answer a message from the contact form. Use this in the message's thread. The reply and the status are saved in the spreadsheet. If the sender gave a Discord username, it's looked up and shown, and with `dm` the reply is also sent to them. Without a status the message is marked as answered. Closed messages can't be answered anymore. Only for mods."]
+#[usage = "[open|answered|closed] [dm] MESSAGE"]
+#[example = "Thanks, we'll look into it!"]
+#[example = "dm Thanks, we'll look into it!"]
+#[example = "closed"]
+async fn reply(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let (status, dm, text) = parse_reply(args.rest());
+    if status.is_none() && text.is_empty() {
+        msg.reply(ctx, "Usage: `!contact reply [open|answered|closed] [dm] MESSAGE`").await?;
+        return Ok(());
+    }
+
+    let data = ctx.data.read().await;
+    if !is_mod(ctx, msg, data.extract::<Config>()?.mods_channel).await {
+        msg.reply(ctx, "Only mods can answer contact form messages.").await?;
+        return Ok(());
+    }
+    let mut contact = match ContactThread::find(&data, msg.channel_id)? {
+        Some(contact) => contact,
+        None => {
+            msg.reply(ctx, "This only works in the thread of an open contact form message.")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Sending the last reply again with `dm` doesn't add it twice.
+    if !text.is_empty() && contact.replies.last().map(String::as_str) != Some(text) {
+        contact.replies.push(text.to_string());
+    }
+    contact.status = status.unwrap_or(Status::Answered);
+    contact.update_spreadsheet(&data).await?;
+    contact.save(&data)?;
+
+    let mut response = format!("Saved, the message is now {}.", contact.status);
+    if contact.status == Status::Closed {
+        response.push_str(" Replies can't be added to it anymore.");
+    }
+    if !text.is_empty() {
+        if let Some(username) = contact.username.as_deref() {
+            let guild = data.extract::<Config>()?.guild;
+            // Named by their tag rather than mentioned, so they're not pulled into the thread.
+            match find_member(ctx, guild, username).await {
+                Some(user) if dm => {
+                    let sent = user
+                        .direct_message(ctx, |m| {
+                            m.content("A reply to your message from the contact form:")
+                                .embed(|embed| embed.description(text))
+                        })
+                        .await
+                        .is_ok();
+                    if sent {
+                        response.push_str(&format!(
+                            " The reply was also sent to {} ({}) in a DM.",
+                            user.tag(),
+                            user.id
+                        ));
+                    } else {
+                        response.push_str(&format!(
+                            " Couldn't send {} ({}) a DM, so you'll have to reach them some other way.",
+                            user.tag(),
+                            user.id
+                        ));
+                    }
+                }
+                Some(user) => response.push_str(&format!(
+                    " The sender looks like {} ({}). If that's them, repeat the reply with `dm` to send it to them.",
+                    user.tag(),
+                    user.id
+                )),
+                None => response.push_str(
+                    " Couldn't find them on the server, so you'll have to reach them some other way.",
+                ),
+            }
+        }
+    }
+    msg.reply(ctx, response).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_reply;
+    use crate::contact::Status;
+
+    #[test]
+    fn reply_args() {
+        assert_eq!(parse_reply("closed"), (Some(Status::Closed), false, ""));
+        assert_eq!(
+            parse_reply("Open  still looking"),
+            (Some(Status::Open), false, "still looking")
+        );
+        assert_eq!(parse_reply("Thanks, noted."), (None, false, "Thanks, noted."));
+        assert_eq!(parse_reply("  "), (None, false, ""));
+        assert_eq!(parse_reply("DM Thanks, noted."), (None, true, "Thanks, noted."));
+        assert_eq!(parse_reply("answered dm  Thanks."), (Some(Status::Answered), true, "Thanks."));
+    }
+}
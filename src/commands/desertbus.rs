@@ -0,0 +1,494 @@
+use crate::desertbus::{
+    format_bussed, DesertBus as DesertBusClient, DesertBusSettings, Progress, SETTINGS,
+};
+use crate::desertbus_history::{self, Run};
+use crate::extract::Extract;
+use crate::time::HumanReadable;
+use chrono::{DateTime, Datelike, Duration, Utc};
+use separator::FixedPlaceSeparatable;
+use serenity::framework::standard::macros::{command, group};
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use serenity::utils::MessageBuilder;
+
+#[group("Desert Bus")]
+#[description = "Commands for Desert Bus for Hope"]
+#[prefix = "desertbus"]
+#[default_command(status)]
+#[commands(status, records, import, settings, set, reset)]
+struct DesertBus;
+
+/// How a past run was doing at the current run's elapsed hour, if the current run is going on.
+fn push_comparison(message: &mut MessageBuilder, progress: &Progress, run: &Run) -> bool {
+    if !progress.has_started() || progress.is_over() {
+        return false;
+    }
+    let hour = progress.bussed().num_hours();
+    match run.at_hour(hour as usize) {
+        Some(total) => {
+            message
+                .push(" At hour ")
+                .push(hour)
+                .push(", Desert Bus ")
+                .push(run.year)
+                .push(" had raised $")
+                .push(total.separated_string_with_fixed_place(2))
+                .push(".");
+            true
+        }
+        None => false,
+    }
+}
+
+fn format_status(progress: &Progress, history: &[Run]) -> String {
+    let mut message = MessageBuilder::new();
+    let total = progress.money_raised.separated_string_with_fixed_place(2);
+
+    if !progress.has_started() {
+        message
+            .push("Desert Bus for Hope starts <t:")
+            .push(progress.start().timestamp())
+            .push(":R>. $")
+            .push(total)
+            .push(" raised so far, enough for ")
+            .push(progress.hours)
+            .push(" hours.");
+    } else if progress.is_over() {
+        message
+            .push("Desert Bus for Hope ended after ")
+            .push(progress.hours)
+            .push(" hours with $")
+            .push(total)
+            .push(" raised.");
+        return message.build();
+    } else {
+        message
+            .push("$")
+            .push(total)
+            .push(" raised. ")
+            .push(format_bussed(progress.bussed()))
+            .push(" hours of ")
+            .push(progress.hours)
+            .push(" bussed so far, ")
+            .push(format_bussed(progress.remaining()))
+            .push(" to go.");
+    }
+
+    message
+        .push(" $")
+        .push(progress.next_hour().separated_string_with_fixed_place(2))
+        .push(" until the next hour.");
+
+    if let Some(projected) = progress.projected_total() {
+        message
+            .push(" Projected final total: $")
+            .push(projected.separated_string_with_fixed_place(2))
+            .push(".");
+    }
+
+    let year = progress.start().year();
+    for run in history.iter().rev().filter(|run| run.year < year) {
+        if push_comparison(&mut message, progress, run) {
+            break;
+        }
+    }
+
+    message.build()
+}
+
+fn format_run(run: &Run, progress: Option<&Progress>) -> String {
+    let mut message = MessageBuilder::new();
+    message
+        .push("Desert Bus for Hope ")
+        .push(run.number())
+        .push(" (")
+        .push(run.year)
+        .push(") raised $")
+        .push(run.total.separated_string_with_fixed_place(2));
+    if let Some(hours) = run.hours {
+        message.push(" over ").push(hours).push(" hours");
+    }
+    if let Some(start) = run.start {
+        message.push(", starting <t:").push(start.timestamp()).push(":D>");
+    }
+    message.push(".");
+
+    if let Some(progress) = progress {
+        push_comparison(&mut message, progress, run);
+    }
+
+    message.build()
+}
+
+fn format_records(history: &[Run], progress: Option<&Progress>) -> String {
+    let mut message = MessageBuilder::new();
+
+    let highest = history.iter().max_by(|a, b| a.total.total_cmp(&b.total));
+    let longest = history.iter().filter(|run| run.hours.is_some()).max_by_key(|run| run.hours);
+    let biggest_increase = history
+        .windows(2)
+        .filter(|runs| runs[0].year + 1 == runs[1].year && runs[0].total < runs[1].total)
+        .max_by(|a, b| (a[1].total - a[0].total).total_cmp(&(b[1].total - b[0].total)));
+
+    match highest {
+        Some(run) => message
+            .push("Highest total: $")
+            .push(run.total.separated_string_with_fixed_place(2))
+            .push(" (")
+            .push(run.year)
+            .push(")."),
+        None => return String::from("No Desert Bus runs on record."),
+    };
+    if let Some(run) = longest {
+        message
+            .push(" Longest run: ")
+            .push(run.hours.unwrap_or_default())
+            .push(" hours (")
+            .push(run.year)
+            .push(").");
+    }
+    if let Some(runs) = biggest_increase {
+        message
+            .push(" Biggest increase: $")
+            .push((runs[1].total - runs[0].total).separated_string_with_fixed_place(2))
+            .push(" (")
+            .push(runs[1].year)
+            .push(").");
+    }
+
+    if let Some(progress) = progress {
+        let year = progress.start().year();
+        if progress.has_started() && history.iter().all(|run| run.year != year) {
+            let rank = 1 + history.iter().filter(|run| run.total > progress.money_raised).count();
+            message
+                .push(" This year's $")
+                .push(progress.money_raised.separated_string_with_fixed_place(2))
+                .push(" so far would rank #")
+                .push(rank)
+                .push(" of ")
+                .push(history.len() + 1)
+                .push(".");
+        }
+    }
+
+    message.build()
+}
+
+/// The current run's settings and total, if it's close enough to matter.
+async fn current_run(ctx: &Context) -> Result<Option<(DesertBusSettings, f64)>, anyhow::Error> {
+    let data = ctx.data.read().await;
+    let settings = DesertBusSettings::load(&data)?;
+    if !settings.in_announce_window(Utc::now()) {
+        return Ok(None);
+    }
+    let money_raised = data.extract::<DesertBusClient>()?.money_raised().await?;
+    Ok(Some((settings, money_raised)))
+}
+
+#[command]
+#[help_available]
+#[description = "Show how Desert Bus for Hope is going, or how it went in a past year."]
+#[usage = "[YEAR]"]
+#[example = ""]
+#[example = "2019"]
+#[max_args(1)]
+async fn status(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let history = {
+        let data = ctx.data.read().await;
+        desertbus_history::history(&data)?
+    };
+
+    if args.is_empty() {
+        let (settings, money_raised) = {
+            let data = ctx.data.read().await;
+            let settings = DesertBusSettings::load(&data)?;
+            let money_raised = data.extract::<DesertBusClient>()?.money_raised().await?;
+            (settings, money_raised)
+        };
+        let progress = settings.progress(money_raised, Utc::now());
+        msg.reply(ctx, format_status(&progress, &history)).await?;
+        return Ok(());
+    }
+
+    let year = match args.single::<i32>() {
+        Ok(year) => year,
+        Err(_) => {
+            msg.reply(ctx, "Usage: `!desertbus [YEAR]`").await?;
+            return Ok(());
+        }
+    };
+    let current_run = current_run(ctx).await?;
+    let progress = current_run
+        .as_ref()
+        .map(|(settings, money_raised)| settings.progress(*money_raised, Utc::now()));
+
+    let reply = match history.iter().find(|run| run.year == year) {
+        Some(run) => format_run(run, progress.as_ref()),
+        None => match progress {
+            Some(ref progress) if progress.start().year() == year => {
+                format_status(progress, &history)
+            }
+            _ => format!("There's no record of Desert Bus for Hope in {}.", year),
+        },
+    };
+    msg.reply(ctx, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[help_available]
+#[description = "Show the Desert Bus for Hope records."]
+#[num_args(0)]
+async fn records(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    let history = {
+        let data = ctx.data.read().await;
+        desertbus_history::history(&data)?
+    };
+    let current_run = current_run(ctx).await?;
+    let progress = current_run
+        .as_ref()
+        .map(|(settings, money_raised)| settings.progress(*money_raised, Utc::now()));
+
+    msg.reply(ctx, format_records(&history, progress.as_ref())).await?;
+
+    Ok(())
+}
+
+#[command]
+#[help_available]
+#[description = "Add a past run or its per-hour totals, so that later runs can be compared to it. Attach a file with a `HOUR,TOTAL` line for each hour, starting from hour 0, from the official stats. Only the final totals up to 2019 are built in."]
+#[usage = "YEAR [START] [HOURS]"]
+#[example = "2019 2019-11-08T18:00:00-08:00 162"]
+#[min_args(1)]
+#[max_args(3)]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+async fn import(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let usage = "Usage: `!desertbus import YEAR [START] [HOURS]` with a file of `HOUR,TOTAL` lines attached";
+    let year = args.single::<i32>();
+    let start = args.single::<String>().ok().map(|start| DateTime::parse_from_rfc3339(&start));
+    let hours = args.single::<i64>().ok();
+    let (year, start, attachment) = match (year, start, msg.attachments.first()) {
+        (Ok(year), None, Some(attachment)) => (year, None, attachment),
+        (Ok(year), Some(Ok(start)), Some(attachment)) => (year, Some(start), attachment),
+        _ => {
+            msg.reply(ctx, usage).await?;
+            return Ok(());
+        }
+    };
+
+    let csv = attachment.download().await?;
+    let hourly = match desertbus_history::parse_hourly(&String::from_utf8_lossy(&csv)) {
+        Ok(hourly) if !hourly.is_empty() => hourly,
+        Ok(_) => {
+            msg.reply(ctx, usage).await?;
+            return Ok(());
+        }
+        Err(error) => {
+            msg.reply(
+                ctx,
+                MessageBuilder::new().push("Could not read the file: ").push_safe(error).build(),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let data = ctx.data.read().await;
+    let known = desertbus_history::history(&data)?.into_iter().find(|run| run.year == year);
+    let run = Run {
+        year,
+        start: start.or_else(|| known.as_ref().and_then(|run| run.start)),
+        total: known
+            .as_ref()
+            .map(|run| run.total)
+            .or_else(|| hourly.iter().rev().find_map(|total| *total))
+            .unwrap_or_default(),
+        hours: hours.or_else(|| known.as_ref().and_then(|run| run.hours)),
+        hourly,
+    };
+    let reply = format!(
+        "Saved {} hours of totals for Desert Bus {}. {}",
+        run.hourly.iter().filter(|total| total.is_some()).count(),
+        year,
+        format_run(&run, None)
+    );
+    desertbus_history::save_run(&data, run)?;
+    msg.reply(ctx, reply).await?;
+
+    Ok(())
+}
+
+fn format_settings(settings: &DesertBusSettings) -> String {
+    MessageBuilder::new()
+        .push("Start: <t:")
+        .push(settings.start.timestamp())
+        .push(":F> (")
+        .push(settings.start.to_rfc3339())
+        .push(if settings.start_is_guess {
+            "; guessed, use `!desertbus set start` to set it"
+        } else {
+            ""
+        })
+        .push(")\nFirst hour: $")
+        .push(settings.first_hour)
+        .push("\nMultiplier: ")
+        .push(settings.multiplier)
+        .push("\nAnnounced from ")
+        .push(HumanReadable::new(Duration::hours(settings.announce_before_hours)))
+        .push(" before the start until ")
+        .push(HumanReadable::new(Duration::hours(settings.announce_after_hours)))
+        .push(" after it")
+        .build()
+}
+
+#[command]
+#[help_available]
+#[description = "Show the settings for the current Desert Bus run."]
+#[num_args(0)]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+async fn settings(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    let settings = {
+        let data = ctx.data.read().await;
+        DesertBusSettings::load(&data)?
+    };
+    msg.reply(ctx, format_settings(&settings)).await?;
+
+    Ok(())
+}
+
+#[command]
+#[help_available]
+#[description = "Change a setting for the current Desert Bus run. The change is kept until `!desertbus reset`."]
+#[usage = "start|first_hour|multiplier|announce_before|announce_after VALUE"]
+#[example = "start 2022-11-11T14:00:00-08:00"]
+#[example = "announce_before 3d"]
+#[num_args(2)]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+async fn set(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let key = args.single::<String>()?;
+    let value = args.single::<String>()?;
+
+    let result = {
+        let data = ctx.data.read().await;
+        let mut settings = DesertBusSettings::load(&data)?;
+        match settings.set(&key, &value) {
+            Ok(()) => {
+                settings.save(&data)?;
+                Ok(settings)
+            }
+            Err(error) => Err(error),
+        }
+    };
+
+    match result {
+        Ok(settings) => msg.reply(ctx, format_settings(&settings)).await?,
+        Err(error) => {
+            msg.reply(
+                ctx,
+                MessageBuilder::new()
+                    .push("Could not change the setting: ")
+                    .push_safe(error)
+                    .push(". The settings are: ")
+                    .push(SETTINGS.join(", "))
+                    .build(),
+            )
+            .await?
+        }
+    };
+
+    Ok(())
+}
+
+#[command]
+#[help_available]
+#[description = "Go back to the Desert Bus settings from the config file."]
+#[num_args(0)]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+async fn reset(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    let settings = {
+        let data = ctx.data.read().await;
+        DesertBusSettings::reset(&data)?;
+        DesertBusSettings::load(&data)?
+    };
+    msg.reply(ctx, format_settings(&settings)).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_records, format_run, format_status};
+    use crate::desertbus::DesertBusSettings;
+    use crate::desertbus_history::Run;
+    use chrono::{DateTime, Utc};
+
+    #[test]
+    fn status() {
+        let settings = DesertBusSettings {
+            start: DateTime::parse_from_rfc3339("2022-11-11T14:00:00-08:00").unwrap(),
+            first_hour: 1.0,
+            multiplier: 2.0,
+            ..DesertBusSettings::default()
+        };
+        let at = |s| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+
+        assert_eq!(
+            format_status(&settings.progress(10.0, at("2022-11-11T21:00:00Z")), &[]),
+            "Desert Bus for Hope starts <t:1668204000:R>. $10.00 raised so far, enough for 3 hours. $5.00 until the next hour."
+        );
+        assert_eq!(
+            format_status(&settings.progress(30.0, at("2022-11-12T00:30:00Z")), &[]),
+            "$30.00 raised. 2:30 hours of 4 bussed so far, 1:30 to go. $1.00 until the next hour. Projected final total: $60.00."
+        );
+        assert_eq!(
+            format_status(&settings.progress(30.0, at("2022-11-12T03:00:00Z")), &[]),
+            "Desert Bus for Hope ended after 4 hours with $30.00 raised."
+        );
+    }
+
+    #[test]
+    fn history() {
+        let settings = DesertBusSettings {
+            start: DateTime::parse_from_rfc3339("2022-11-11T14:00:00-08:00").unwrap(),
+            first_hour: 1.0,
+            multiplier: 2.0,
+            ..DesertBusSettings::default()
+        };
+        let progress = settings.progress(
+            30.0,
+            DateTime::parse_from_rfc3339("2022-11-12T00:30:00Z").unwrap().with_timezone(&Utc),
+        );
+        let history = vec![
+            Run { year: 2019, start: None, total: 40.0, hours: None, hourly: vec![] },
+            Run {
+                year: 2020,
+                start: Some(DateTime::parse_from_rfc3339("2020-11-13T18:00:00-08:00").unwrap()),
+                total: 25.0,
+                hours: Some(4),
+                hourly: vec![Some(5.0), Some(10.0), Some(15.0), Some(20.0), Some(25.0)],
+            },
+        ];
+
+        assert!(format_status(&progress, &history).ends_with(
+            "Projected final total: $60.00. At hour 2, Desert Bus 2020 had raised $15.00."
+        ));
+        assert_eq!(
+            format_run(&history[0], Some(&progress)),
+            "Desert Bus for Hope 13 (2019) raised $40.00."
+        );
+        assert_eq!(
+            format_run(&history[1], Some(&progress)),
+            "Desert Bus for Hope 14 (2020) raised $25.00 over 4 hours, starting <t:1605319200:D>. At hour 2, Desert Bus 2020 had raised $15.00."
+        );
+        assert_eq!(
+            format_records(&history, Some(&progress)),
+            "Highest total: $40.00 (2019). Longest run: 4 hours (2020). This year's $30.00 so far would rank #2 of 3."
+        );
+    }
+}
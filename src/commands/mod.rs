@@ -1,9 +1,32 @@
+use serenity::framework::standard::CommandGroup;
+
 pub mod calendar;
+pub mod contact;
 pub mod date;
+pub mod desertbus;
 pub mod help;
 pub mod live;
+pub mod notify;
 pub mod quote;
 pub mod static_response;
+pub mod tempchannel;
 pub mod time;
+pub mod topic;
 pub mod tracing;
 pub mod voice;
+
+/// All the command groups registered with the framework.
+pub static GROUPS: &[&CommandGroup] = &[
+    &calendar::CALENDAR_GROUP,
+    &contact::CONTACT_GROUP,
+    &date::DATE_GROUP,
+    &desertbus::DESERTBUS_GROUP,
+    &live::FANSTREAMS_GROUP,
+    &notify::NOTIFY_GROUP,
+    &quote::QUOTE_GROUP,
+    &tempchannel::TEMPCHANNELS_GROUP,
+    &time::TIME_GROUP,
+    &topic::TOPIC_GROUP,
+    &tracing::TRACING_GROUP,
+    &voice::VOICE_GROUP,
+];
@@ -0,0 +1,79 @@
+use crate::config::Config;
+use crate::extract::Extract;
+use serenity::framework::standard::macros::{command, group};
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use serenity::utils::MessageBuilder;
+
+/// The pseudo-show that selects the role mentioned for every show.
+const ALL_SHOWS: &str = "all";
+
+#[group("Notify")]
+#[description = "Stream notification commands"]
+#[commands(notify)]
+struct Notify;
+
+#[command]
+#[only_in(guilds)]
+#[description = "Toggle getting mentioned when a show goes live. Lists the available shows when called without arguments."]
+#[usage = "[SHOW]"]
+#[example = "talkshow"]
+#[max_args(1)]
+async fn notify(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let data = ctx.data.read().await;
+    let config = data.extract::<Config>()?;
+
+    let show = args.rest().trim().to_lowercase();
+    let role = if show == ALL_SHOWS {
+        config.stream_up_role
+    } else {
+        config.show_roles.get(&show).copied()
+    };
+
+    let role = match role {
+        Some(role) => role,
+        None => {
+            let mut shows = config.show_roles.keys().map(String::as_str).collect::<Vec<_>>();
+            if config.stream_up_role.is_some() {
+                shows.push(ALL_SHOWS);
+            }
+            shows.sort_unstable();
+
+            let mut builder = MessageBuilder::new();
+            if !show.is_empty() {
+                builder.push("Unknown show. ");
+            }
+            if shows.is_empty() {
+                builder.push("There are no shows to get notified about.");
+            } else {
+                builder.push("Available shows: ");
+                for (i, show) in shows.iter().enumerate() {
+                    if i != 0 {
+                        builder.push(", ");
+                    }
+                    builder.push_mono_safe(show);
+                }
+            }
+            msg.reply(ctx, builder.build()).await?;
+            return Ok(());
+        }
+    };
+
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(()),
+    };
+    let mut member = guild_id.member(ctx, msg.author.id).await?;
+
+    let reply = if member.roles.contains(&role) {
+        member.remove_role(ctx, role).await?;
+        "You will no longer be notified when the show goes live."
+    } else {
+        member.add_role(ctx, role).await?;
+        "You will now be notified when the show goes live."
+    };
+    msg.reply(ctx, reply).await?;
+
+    Ok(())
+}
@@ -1,19 +1,58 @@
+use crate::config::Config;
+use crate::emoji::{EmojiCache, EmojiMatcher};
 use crate::extract::Extract;
 use crate::rpc::LRRbot;
 use anyhow::{Context as _, Error};
 use rand::seq::SliceRandom;
-use regex::Regex;
 use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use serenity::builder::{CreateMessage, ParseValue};
 use serenity::framework::standard::macros::hook;
-use serenity::framework::standard::{Args, Delimiter};
+use serenity::framework::standard::{Args, CommandGroup, Delimiter};
+use serenity::http::AttachmentType;
 use serenity::model::channel::Message;
-use serenity::model::guild::Emoji;
+use serenity::model::id::StickerId;
 use serenity::prelude::*;
-use serenity::utils::Colour;
+use serenity::utils::{Colour, MessageBuilder};
 use std::borrow::Cow;
+use std::cmp;
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{error, info};
 
+// Only suggest commands that are at most `MAX_SUGGESTION_DISTANCE` edits away from what was typed,
+// and at most one edit per `SUGGESTION_CHARS_PER_EDIT` characters so that short typos don't match
+// everything.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+const SUGGESTION_CHARS_PER_EDIT: usize = 3;
+/// How long the names of the static responses are reused for suggestions before refetching them.
+const RESPONSE_NAMES_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// The names of LRRbot's static responses, so that unknown commands don't each fetch them all.
+#[derive(Default)]
+pub struct ResponseNames {
+    names: RwLock<Option<(Instant, Arc<Vec<String>>)>>,
+}
+
+impl ResponseNames {
+    async fn get(&self, lrrbot: &LRRbot) -> Result<Arc<Vec<String>>, Error> {
+        if let Some((fetched, ref names)) = *self.names.read().await {
+            if fetched.elapsed() < RESPONSE_NAMES_TTL {
+                return Ok(names.clone());
+            }
+        }
+
+        let responses = lrrbot
+            .get_data::<HashMap<String, serde_json::Value>>(vec![String::from("responses")])
+            .await
+            .context("failed to fetch the commands")?;
+        let names = Arc::new(responses.into_keys().collect::<Vec<_>>());
+        *self.names.write().await = Some((Instant::now(), names.clone()));
+        Ok(names)
+    }
+}
+
 #[derive(Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
 #[serde(rename_all = "lowercase")]
 enum Access {
@@ -62,30 +101,61 @@ impl Access {
 enum Response {
     Some {
         access: Access,
-        #[serde(deserialize_with = "string_or_seq_string")]
-        response: Vec<String>,
+        #[serde(deserialize_with = "reply_or_seq_reply")]
+        response: Vec<Reply>,
     },
     None {},
 }
 
-fn string_or_seq_string<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+enum Reply {
+    Text(String),
+    Embed(EmbedReply),
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+struct EmbedReply {
+    /// Text posted above the embed.
+    content: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    #[serde(alias = "color")]
+    colour: Option<u32>,
+    /// URL of the embed image.
+    image: Option<String>,
+    #[serde(default)]
+    fields: Vec<EmbedField>,
+    /// URL of a file to attach to the message.
+    file: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+struct EmbedField {
+    name: String,
+    value: String,
+    #[serde(default)]
+    inline: bool,
+}
+
+fn reply_or_seq_reply<'de, D>(deserializer: D) -> Result<Vec<Reply>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    struct StringOrVec;
+    struct ReplyOrVec;
 
-    impl<'de> serde::de::Visitor<'de> for StringOrVec {
-        type Value = Vec<String>;
+    impl<'de> serde::de::Visitor<'de> for ReplyOrVec {
+        type Value = Vec<Reply>;
 
         fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-            formatter.write_str("string or list of strings")
+            formatter.write_str("string, embed or list of strings and embeds")
         }
 
         fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
         where
             E: serde::de::Error,
         {
-            Ok(vec![value.to_owned()])
+            Ok(vec![Reply::Text(value.to_owned())])
         }
 
         fn visit_seq<S>(self, visitor: S) -> Result<Self::Value, S::Error>
@@ -94,29 +164,262 @@ where
         {
             Deserialize::deserialize(serde::de::value::SeqAccessDeserializer::new(visitor))
         }
+
+        fn visit_map<M>(self, visitor: M) -> Result<Self::Value, M::Error>
+        where
+            M: serde::de::MapAccess<'de>,
+        {
+            let embed =
+                Deserialize::deserialize(serde::de::value::MapAccessDeserializer::new(visitor))?;
+            Ok(vec![Reply::Embed(embed)])
+        }
     }
 
-    deserializer.deserialize_any(StringOrVec)
+    deserializer.deserialize_any(ReplyOrVec)
+}
+
+/// Substitutes the variables and the guild emojis in the text fields of a reply.
+struct ReplyFormatter<'a> {
+    vars: HashMap<String, Cow<'a, str>>,
+    emojis: Option<Arc<EmojiMatcher>>,
 }
 
-fn replace_emojis<'a, S: Into<String>, I: Iterator<Item = &'a Emoji>>(
-    msg: S,
-    emojis: I,
-) -> Result<String, Error> {
-    let mut msg = msg.into();
-
-    for emoji in emojis {
-        let regex = Regex::new(&format!(r"\b{}\b", regex::escape(&emoji.name)))
-            .with_context(|| format!("invalid regex syntax with {:?}", emoji.name))?;
-        if let Cow::Owned(s) = regex.replace_all(&msg, emoji.mention().to_string().as_str()) {
-            msg = s;
+impl ReplyFormatter<'_> {
+    fn format(&self, text: &str) -> Result<String, Error> {
+        let text = strfmt::strfmt(text, &self.vars).context("failed to format the reply")?;
+        match self.emojis {
+            Some(ref emojis) => Ok(emojis.replace(&text).into_owned()),
+            None => Ok(text),
         }
     }
 
-    Ok(msg)
+    /// Like `format`, but takes out the names of guild stickers to send with the message.
+    fn format_with_stickers(&self, text: &str) -> Result<(String, Vec<StickerId>), Error> {
+        let text = strfmt::strfmt(text, &self.vars).context("failed to format the reply")?;
+        match self.emojis {
+            Some(ref emojis) => Ok(emojis.replace_with_stickers(&text)),
+            None => Ok((text, vec![])),
+        }
+    }
 }
 
-async fn static_response_impl(ctx: &Context, msg: &Message, command: &str) -> Result<(), Error> {
+/// Adds stickers to a message. Serenity doesn't know about them yet.
+fn add_stickers(m: &mut CreateMessage, stickers: &[StickerId]) {
+    if !stickers.is_empty() {
+        let ids = stickers.iter().map(|id| Value::from(id.0.to_string())).collect();
+        m.0.insert("sticker_ids", Value::Array(ids));
+    }
+}
+
+async fn send_text(
+    ctx: &Context,
+    msg: &Message,
+    formatter: &ReplyFormatter<'_>,
+    text: &str,
+) -> Result<(), Error> {
+    let (text, stickers) = formatter.format_with_stickers(text)?;
+    if stickers.is_empty() {
+        msg.reply(ctx, text).await.context("failed to send a reply")?;
+        return Ok(());
+    }
+
+    // The same as `Message::reply`, plus the stickers.
+    msg.channel_id
+        .send_message(ctx, |m| {
+            m.reference_message(msg).allowed_mentions(|am| {
+                am.replied_user(false)
+                    .parse(ParseValue::Everyone)
+                    .parse(ParseValue::Users)
+                    .parse(ParseValue::Roles)
+            });
+            if !text.is_empty() {
+                m.content(text);
+            }
+            add_stickers(m, &stickers);
+            m
+        })
+        .await
+        .context("failed to send a reply")?;
+
+    Ok(())
+}
+
+async fn send_embed(
+    ctx: &Context,
+    msg: &Message,
+    formatter: &ReplyFormatter<'_>,
+    embed: &EmbedReply,
+) -> Result<(), Error> {
+    let mut content = MessageBuilder::new();
+    content.mention(&msg.author);
+    let mut stickers = vec![];
+    if let Some(ref text) = embed.content {
+        let (text, content_stickers) = formatter.format_with_stickers(text)?;
+        content.push(": ").push(text);
+        stickers = content_stickers;
+    }
+    let content = content.build();
+    let title = embed.title.as_deref().map(|title| formatter.format(title)).transpose()?;
+    let description =
+        embed.description.as_deref().map(|desc| formatter.format(desc)).transpose()?;
+    let fields = embed
+        .fields
+        .iter()
+        .map(|field| {
+            Ok((formatter.format(&field.name)?, formatter.format(&field.value)?, field.inline))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let has_embed =
+        title.is_some() || description.is_some() || embed.image.is_some() || !fields.is_empty();
+
+    msg.channel_id
+        .send_message(ctx, |m| {
+            m.content(content);
+            // Discord rejects empty embeds, eg. when the reply is just a file.
+            if has_embed {
+                m.embed(|e| {
+                    if let Some(title) = title {
+                        e.title(title);
+                    }
+                    if let Some(description) = description {
+                        e.description(description);
+                    }
+                    if let Some(colour) = embed.colour {
+                        e.colour(colour);
+                    }
+                    if let Some(ref image) = embed.image {
+                        e.image(image);
+                    }
+                    e.fields(fields)
+                });
+            }
+            if let Some(ref file) = embed.file {
+                m.add_file(AttachmentType::Image(file));
+            }
+            add_stickers(m, &stickers);
+            m
+        })
+        .await
+        .context("failed to send a reply")?;
+
+    Ok(())
+}
+
+fn framework_command_names(groups: &[&'static CommandGroup]) -> Vec<String> {
+    let mut names = vec![];
+
+    for group in groups {
+        if !group.options.help_available {
+            continue;
+        }
+
+        let prefixes = group.options.prefixes;
+        if group.options.default_command.is_some() {
+            names.extend(prefixes.iter().map(|&prefix| String::from(prefix)));
+        }
+
+        for command in group.options.commands {
+            if !command.options.help_available {
+                continue;
+            }
+
+            for &name in command.options.names {
+                if prefixes.is_empty() {
+                    names.push(String::from(name));
+                } else {
+                    names.extend(prefixes.iter().map(|prefix| format!("{} {}", prefix, name)));
+                }
+            }
+        }
+
+        names.extend(framework_command_names(group.options.sub_groups));
+    }
+
+    names
+}
+
+/// Find the candidates closest to any of the `typed` strings.
+fn suggestions<'a, I: IntoIterator<Item = &'a str>>(typed: &[&str], candidates: I) -> Vec<&'a str> {
+    let typed = typed.iter().map(|typed| typed.to_lowercase()).collect::<Vec<_>>();
+
+    let mut best_distance = usize::MAX;
+    let mut best = vec![];
+
+    for candidate in candidates {
+        let candidate_lower = candidate.to_lowercase();
+        let distance = typed
+            .iter()
+            .filter_map(|typed| {
+                let max_distance = cmp::min(
+                    MAX_SUGGESTION_DISTANCE,
+                    typed.chars().count() / SUGGESTION_CHARS_PER_EDIT,
+                );
+                let distance = levenshtein::levenshtein(typed, &candidate_lower);
+                if distance <= max_distance {
+                    Some(distance)
+                } else {
+                    None
+                }
+            })
+            .min();
+
+        match distance {
+            Some(distance) if distance < best_distance => {
+                best_distance = distance;
+                best = vec![candidate];
+            }
+            Some(distance) if distance == best_distance => best.push(candidate),
+            _ => (),
+        }
+    }
+
+    best.sort_unstable();
+    best.dedup();
+    best
+}
+
+async fn suggest_command(
+    ctx: &Context,
+    msg: &Message,
+    command_name: &str,
+    command: &str,
+) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+    let prefix = &data.extract::<Config>()?.command_prefix;
+
+    let responses = data.extract::<ResponseNames>()?.get(data.extract::<LRRbot>()?).await?;
+
+    let mut candidates = framework_command_names(crate::commands::GROUPS);
+    candidates.extend(crate::commands::help::HELP.options.names.iter().map(|&name| name.into()));
+    candidates.extend(responses.iter().cloned());
+
+    let suggestions = suggestions(&[command_name, command], candidates.iter().map(String::as_str));
+    if suggestions.is_empty() {
+        return Ok(());
+    }
+
+    let mut builder = MessageBuilder::new();
+    builder.push("Unknown command. Did you mean ");
+    for (i, suggestion) in suggestions.iter().enumerate() {
+        if i != 0 {
+            builder.push(if i == suggestions.len() - 1 { " or " } else { ", " });
+        }
+        builder.push_mono_safe(format!("{}{}", prefix, suggestion));
+    }
+    builder.push("?");
+
+    msg.reply(ctx, builder.build()).await.context("failed to send a reply")?;
+
+    Ok(())
+}
+
+async fn static_response_impl(
+    ctx: &Context,
+    msg: &Message,
+    command_name: &str,
+    command: &str,
+) -> Result<(), Error> {
     info!(
         command_name = command,
         message = msg.content.as_str(),
@@ -153,15 +456,25 @@ async fn static_response_impl(ctx: &Context, msg: &Message, command: &str) -> Re
                         msg.author.name.as_str().into()
                     },
                 );
-                let response =
-                    strfmt::strfmt(response, &vars).context("failed to format the reply")?;
-                let response = if let Some(guild) = msg.guild(&ctx).await {
-                    replace_emojis(response, guild.emojis.values())
-                        .context("failed to replace emojis")?
+                let emojis = if let Some(guild) = msg.guild(&ctx).await {
+                    let matcher = ctx
+                        .data
+                        .read()
+                        .await
+                        .extract::<EmojiCache>()?
+                        .get(&guild)
+                        .await
+                        .context("failed to build the emoji matcher")?;
+                    Some(matcher)
                 } else {
-                    response
+                    None
                 };
-                msg.reply(ctx, &response).await.context("failed to send a reply")?;
+                let formatter = ReplyFormatter { vars, emojis };
+
+                match response {
+                    Reply::Text(text) => send_text(ctx, msg, &formatter, text).await?,
+                    Reply::Embed(embed) => send_embed(ctx, msg, &formatter, embed).await?,
+                }
             }
         } else {
             info!(
@@ -170,6 +483,8 @@ async fn static_response_impl(ctx: &Context, msg: &Message, command: &str) -> Re
                 "Refusing to reply because user lacks access"
             );
         }
+    } else {
+        suggest_command(ctx, msg, command_name, command).await?;
     }
 
     Ok(())
@@ -177,7 +492,7 @@ async fn static_response_impl(ctx: &Context, msg: &Message, command: &str) -> Re
 
 #[hook]
 pub async fn static_response(ctx: &Context, msg: &Message, command: &str) {
-    match static_response_impl(ctx, msg, &extract_command(&msg.content, command)).await {
+    match static_response_impl(ctx, msg, command, &extract_command(&msg.content, command)).await {
         Ok(()) => (),
         Err(error) => {
             error!(message.id = msg.id.0, ?error, "Static command resulted in an unexpected error");
@@ -222,7 +537,7 @@ fn test_deserialize_single_response() {
         res,
         Response::Some {
             access: Access::Any,
-            response: vec!["Help: https://lrrbot.com/help".into()]
+            response: vec![Reply::Text("Help: https://lrrbot.com/help".into())]
         }
     );
 }
@@ -234,14 +549,16 @@ fn test_deserialize_multi_response() {
             .unwrap();
     assert_eq!(
         res,
-        Response::Some { access: Access::Sub, response: vec!["peach".into(), "barf".into()] }
+        Response::Some {
+            access: Access::Sub,
+            response: vec![Reply::Text("peach".into()), Reply::Text("barf".into())]
+        }
     );
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Response;
-    use serenity::model::guild::Emoji;
+    use super::{Access, EmbedField, EmbedReply, Reply, Response};
 
     #[test]
     fn deserialize_missing() {
@@ -250,46 +567,64 @@ mod tests {
     }
 
     #[test]
-    fn replace_emojis() {
-        let emoji = serde_json::from_str::<Vec<Emoji>>(
-            r#"
-            [
-                {
-                    "animated": false,
-                    "id": "1",
-                    "name": "lrrDOTS",
-                    "managed": true,
-                    "require_colons": true,
-                    "roles": []
-                },
-                {
-                    "animated": false,
-                    "id": "2",
-                    "name": "lrrCIRCLE",
-                    "managed": true,
-                    "require_colons": true,
-                    "roles": []
-                },
-                {
-                    "animated": false,
-                    "id": "3",
-                    "name": "lrrARROW",
-                    "managed": true,
-                    "require_colons": true,
-                    "roles": []
+    fn deserialize_embed() {
+        let res = serde_json::from_str::<Response>(
+            r#"{
+                "access": "any",
+                "response": {
+                    "title": "Schedule",
+                    "description": "{user}: lrrSPOT",
+                    "color": 16711680,
+                    "image": "https://example.com/schedule.png",
+                    "fields": [{"name": "Monday", "value": "Talk Show", "inline": true}]
                 }
-            ]
-        "#,
+            }"#,
         )
         .unwrap();
+        assert_eq!(
+            res,
+            Response::Some {
+                access: Access::Any,
+                response: vec![Reply::Embed(EmbedReply {
+                    content: None,
+                    title: Some("Schedule".into()),
+                    description: Some("{user}: lrrSPOT".into()),
+                    colour: Some(0xFF0000),
+                    image: Some("https://example.com/schedule.png".into()),
+                    fields: vec![EmbedField {
+                        name: "Monday".into(),
+                        value: "Talk Show".into(),
+                        inline: true
+                    }],
+                    file: None,
+                })]
+            }
+        );
+    }
 
+    #[test]
+    fn deserialize_mixed() {
+        let res = serde_json::from_str::<Response>(
+            r#"{"access": "mod", "response": ["peach", {"file": "https://example.com/barf.gif"}]}"#,
+        )
+        .unwrap();
         assert_eq!(
-            super::replace_emojis(
-                "lrrDOTS lrrCIRCLE lrrARROW Visit LoadingReadyRun: http://loadingreadyrun.com/",
-                emoji.iter()
-            )
-            .unwrap(),
-            "<:_:1> <:_:2> <:_:3> Visit LoadingReadyRun: http://loadingreadyrun.com/"
+            res,
+            Response::Some {
+                access: Access::Mod,
+                response: vec![
+                    Reply::Text("peach".into()),
+                    Reply::Embed(EmbedReply {
+                        content: None,
+                        title: None,
+                        description: None,
+                        colour: None,
+                        image: None,
+                        fields: vec![],
+                        file: Some("https://example.com/barf.gif".into()),
+                    })
+                ]
+            }
         );
     }
 
@@ -299,4 +634,31 @@ mod tests {
         assert_eq!(super::extract_command("!command", "command"), "command");
         assert_eq!(super::extract_command("<@!1234> some command", "some"), "some command");
     }
+
+    #[test]
+    fn suggestions() {
+        let candidates = ["advice", "advise me", "quote", "quote details", "next", "nextfan"];
+
+        assert_eq!(super::suggestions(&["advise"], candidates.iter().copied()), vec!["advice"]);
+        assert_eq!(super::suggestions(&["ADVISE"], candidates.iter().copied()), vec!["advice"]);
+        assert_eq!(
+            super::suggestions(&["qoute", "qoute details"], candidates.iter().copied()),
+            vec!["quote details"]
+        );
+        assert_eq!(super::suggestions(&["nxt"], candidates.iter().copied()), vec!["next"]);
+        assert_eq!(super::suggestions(&["nextfans"], candidates.iter().copied()), vec!["nextfan"]);
+        // Too short to be a typo of anything.
+        assert!(super::suggestions(&["nx"], candidates.iter().copied()).is_empty());
+        assert!(super::suggestions(&["butts"], candidates.iter().copied()).is_empty());
+    }
+
+    #[test]
+    fn framework_command_names() {
+        let names = super::framework_command_names(crate::commands::GROUPS);
+        assert!(names.iter().any(|name| name == "quote"));
+        assert!(names.iter().any(|name| name == "findquote"));
+        assert!(names.iter().any(|name| name == "quote details"));
+        assert!(!names.iter().any(|name| name.contains("query_debugger")));
+        assert!(!names.iter().any(|name| name == "tracing_filter"));
+    }
 }
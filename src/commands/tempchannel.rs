@@ -0,0 +1,153 @@
+use crate::channel_expiry::{self, ExpiringChannel, Kind};
+use crate::config::Config;
+use crate::extract::Extract;
+use crate::time::parse_duration;
+use anyhow::Context as _;
+use chrono::{DateTime, Duration, Utc};
+use serenity::framework::standard::macros::{command, group};
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use serenity::utils::MessageBuilder;
+
+#[group("Temporary channels")]
+#[description = "Commands for text channels and threads that go away on their own"]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_CHANNELS")]
+#[commands(tempchannel, tempthread)]
+struct TempChannels;
+
+/// Splits `NAME DURATION [export]`.
+fn parse_args(args: &str) -> Option<(&str, Duration, bool)> {
+    let (rest, last) = args.trim().rsplit_once(char::is_whitespace)?;
+    let (rest, export) =
+        if last.eq_ignore_ascii_case("export") { (rest, true) } else { (args.trim(), false) };
+    let (name, duration) = rest.trim_end().rsplit_once(char::is_whitespace)?;
+    let duration = parse_duration(duration).filter(|duration| *duration > Duration::zero())?;
+    Some((name.trim_end(), duration, export))
+}
+
+async fn reply_usage(ctx: &Context, msg: &Message, command: &str) -> CommandResult {
+    msg.reply(
+        ctx,
+        format!(
+            "Usage: `!{} NAME DURATION [export]`, for example `!{} game-night 6h export`",
+            command, command
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn start(
+    ctx: &Context,
+    msg: &Message,
+    channel: GuildChannel,
+    kind: Kind,
+    expires_at: DateTime<Utc>,
+    export: bool,
+) -> CommandResult {
+    let expiring = ExpiringChannel {
+        id: channel.id,
+        name: channel.name.clone(),
+        kind,
+        expires_at,
+        export,
+        exported: false,
+        export_attempts: 0,
+    };
+    {
+        let data = ctx.data.read().await;
+        expiring.add(&data)?;
+    }
+    channel.say(ctx, channel_expiry::notice(&expiring)).await?;
+    msg.reply(ctx, MessageBuilder::new().push("Created ").channel(channel.id).build()).await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Create a text channel that's deleted after a while. With `export`, its history is sent to the mods channel first."]
+#[usage = "NAME DURATION [export]"]
+#[example = "game-night 6h"]
+#[example = "watch-along 1d export"]
+#[min_args(2)]
+async fn tempchannel(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let (name, duration, export) = match parse_args(args.rest()) {
+        Some(args) => args,
+        None => return reply_usage(ctx, msg, "tempchannel").await,
+    };
+    let expires_at = match Utc::now().checked_add_signed(duration) {
+        Some(expires_at) => expires_at,
+        None => return reply_usage(ctx, msg, "tempchannel").await,
+    };
+
+    let guild = msg.guild_id.context("not in a guild")?;
+    let category = {
+        let data = ctx.data.read().await;
+        match data.extract::<Config>()?.temp_text_category {
+            Some(category) => Some(category),
+            None => msg
+                .channel_id
+                .to_channel(ctx)
+                .await?
+                .guild()
+                .and_then(|channel| channel.category_id),
+        }
+    };
+    let channel = guild
+        .create_channel(ctx, |c| {
+            c.name(name).kind(ChannelType::Text);
+            if let Some(category) = category {
+                c.category(category);
+            }
+            c
+        })
+        .await?;
+
+    start(ctx, msg, channel, Kind::Channel, expires_at, export).await
+}
+
+#[command]
+#[description = "Create a thread in this channel that's archived after a while. With `export`, its history is sent to the mods channel first."]
+#[usage = "NAME DURATION [export]"]
+#[example = "Hades spoilers 2h"]
+#[min_args(2)]
+async fn tempthread(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let (name, duration, export) = match parse_args(args.rest()) {
+        Some(args) => args,
+        None => return reply_usage(ctx, msg, "tempthread").await,
+    };
+    let expires_at = match Utc::now().checked_add_signed(duration) {
+        Some(expires_at) => expires_at,
+        None => return reply_usage(ctx, msg, "tempthread").await,
+    };
+
+    // Archive after a day of inactivity, the shortest that doesn't get in the way of most events.
+    let thread = msg
+        .channel_id
+        .create_public_thread(ctx, msg.id, |t| t.name(name).auto_archive_duration(1440))
+        .await?;
+
+    start(ctx, msg, thread, Kind::Thread, expires_at, export).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_args;
+    use chrono::Duration;
+
+    #[test]
+    fn args() {
+        assert_eq!(parse_args("game-night 6h"), Some(("game-night", Duration::hours(6), false)));
+        assert_eq!(
+            parse_args("Hades spoilers 1d12h export"),
+            Some(("Hades spoilers", Duration::hours(36), true))
+        );
+        assert_eq!(parse_args("export 2h"), Some(("export", Duration::hours(2), false)));
+        assert_eq!(parse_args("game-night"), None);
+        assert_eq!(parse_args("game-night soon"), None);
+        assert_eq!(parse_args("game-night 0m"), None);
+        assert_eq!(parse_args("6h export"), None);
+    }
+}
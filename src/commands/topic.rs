@@ -0,0 +1,166 @@
+use crate::autotopic::{self, TopicOverride};
+use crate::config::Config;
+use crate::extract::Extract;
+use crate::time::{parse_duration, HumanReadable};
+use chrono::{Duration, Utc};
+use serenity::framework::standard::macros::{command, group};
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use serenity::utils::MessageBuilder;
+
+#[group("Topic")]
+#[description = "Commands for managing automatic channel topics"]
+#[prefix = "topic"]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_CHANNELS")]
+#[commands(preview, topic_override, resume)]
+struct Topic;
+
+async fn is_managed(ctx: &Context, channel: ChannelId) -> Result<bool, anyhow::Error> {
+    let data = ctx.data.read().await;
+    Ok(data.extract::<Config>()?.topics.iter().any(|topic| topic.channel == channel))
+}
+
+#[command]
+#[help_available]
+#[description = "Show what the topic of this channel would be set to now."]
+#[num_args(0)]
+async fn preview(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    let topic = {
+        let data = ctx.data.read().await;
+        autotopic::preview(&data, msg.channel_id).await?
+    };
+
+    match topic {
+        Some(topic) => {
+            msg.reply(ctx, MessageBuilder::new().push("Topic: ").push_safe(topic).build()).await?;
+        }
+        None if is_managed(ctx, msg.channel_id).await? => {
+            msg.reply(ctx, "The topic would be left as is.").await?;
+        }
+        None => {
+            msg.reply(ctx, "The topic of this channel is not managed automatically.").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits `TEXT [DURATION]`. A topic that ends in something that looks like a duration can be
+/// quoted to keep it whole.
+fn parse_override(text: &str) -> (&str, Option<Duration>) {
+    let text = text.trim();
+    let duration = |s: &str| parse_duration(s).filter(|duration| *duration > Duration::zero());
+
+    if let Some(quoted) = text.strip_prefix('"') {
+        if let Some((topic, rest)) = quoted.rsplit_once('"') {
+            let rest = rest.trim();
+            if rest.is_empty() {
+                return (topic, None);
+            }
+            if let Some(duration) = duration(rest) {
+                return (topic, Some(duration));
+            }
+        }
+    }
+
+    match text.rsplit_once(char::is_whitespace) {
+        Some((topic, last)) => match duration(last) {
+            Some(duration) => (topic.trim_end(), Some(duration)),
+            None => (text, None),
+        },
+        None => (text, None),
+    }
+}
+
+#[command("override")]
+#[help_available]
+#[description = "Set the topic of this channel and stop updating it automatically, optionally only for a while. Put the topic in quotes if it ends in something that looks like a duration."]
+#[usage = "TEXT [DURATION]"]
+#[example = "Welcome to the Desert Bus afterparty! 12h"]
+#[example = "\"Desert Bus starts in 3d\""]
+#[min_args(1)]
+async fn topic_override(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    if !is_managed(ctx, msg.channel_id).await? {
+        msg.reply(ctx, "The topic of this channel is not managed automatically.").await?;
+        return Ok(());
+    }
+
+    let (topic, duration) = parse_override(args.rest());
+    let until = match duration {
+        Some(duration) => match Utc::now().checked_add_signed(duration) {
+            Some(until) => Some(until),
+            None => {
+                msg.reply(ctx, "That's too far in the future.").await?;
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+
+    let topic_override = TopicOverride { topic: String::from(topic), until };
+    {
+        let data = ctx.data.read().await;
+        topic_override.set(&data, msg.channel_id)?;
+    }
+    topic_override.apply(ctx, msg.channel_id).await?;
+
+    let reply = match duration {
+        Some(duration) => format!(
+            "Topic set. Automatic updates are suspended for {}, or until `!topic resume`.",
+            HumanReadable::new(duration)
+        ),
+        None => String::from("Topic set. Automatic updates are suspended until `!topic resume`."),
+    };
+    msg.reply(ctx, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[help_available]
+#[description = "Resume updating the topic of this channel automatically."]
+#[num_args(0)]
+async fn resume(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    if !is_managed(ctx, msg.channel_id).await? {
+        msg.reply(ctx, "The topic of this channel is not managed automatically.").await?;
+        return Ok(());
+    }
+
+    {
+        let data = ctx.data.read().await;
+        TopicOverride::clear(&data, msg.channel_id)?;
+    }
+    msg.reply(ctx, "Automatic topic updates resumed.").await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_override;
+    use chrono::Duration;
+
+    #[test]
+    fn override_args() {
+        assert_eq!(parse_override("Game night!"), ("Game night!", None));
+        assert_eq!(parse_override("Game night! 12h"), ("Game night!", Some(Duration::hours(12))));
+        assert_eq!(parse_override("Desert Bus 2022"), ("Desert Bus 2022", None));
+        assert_eq!(parse_override("Back in 0m"), ("Back in 0m", None));
+        // The last word looks like a duration, so it's taken as one unless the topic is quoted.
+        assert_eq!(
+            parse_override("Desert Bus starts in 3d"),
+            ("Desert Bus starts in", Some(Duration::days(3)))
+        );
+        assert_eq!(
+            parse_override("\"Desert Bus starts in 3d\""),
+            ("Desert Bus starts in 3d", None)
+        );
+        assert_eq!(
+            parse_override(" \"Desert Bus starts in 3d\" 1d "),
+            ("Desert Bus starts in 3d", Some(Duration::days(1)))
+        );
+        assert_eq!(parse_override("\"Quoted\" and not"), ("\"Quoted\" and not", None));
+    }
+}
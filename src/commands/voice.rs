@@ -1,34 +1,335 @@
 use crate::config::Config;
 use crate::extract::Extract;
+use crate::models::TempChannel;
+use crate::typemap_keys::PgPool;
+use anyhow::Context as _;
+use chrono::Utc;
 use serenity::framework::standard::macros::{command, group};
 use serenity::framework::standard::{Args, CommandResult};
 use serenity::model::prelude::*;
 use serenity::prelude::*;
+use serenity::utils::MessageBuilder;
+use std::collections::HashMap;
 
 #[group("Voice")]
 #[description = "Voice channel commands"]
-#[commands(voice)]
+#[prefix = "voice"]
+#[default_command(voice)]
+#[commands(rename, limit, lock, unlock, transfer)]
 struct Voice;
 
 #[command]
-#[description = "Create a temporary voice channel. Unused temporary voice channels will be automatically deleted if they're older than 15 minutes."]
+#[description = "Create a temporary voice channel. Temporary voice channels are deleted automatically once they've been empty for a while."]
 #[usage = "CHANNEL NAME"]
 #[example = "PUBG #15"]
 pub async fn voice(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     let data = ctx.data.read().await;
     let config = data.extract::<Config>()?;
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a database connection from the pool")?;
 
-    let name = format!("{} {}", config.temp_channel_prefix, args.rest().trim());
-    let reply = match config
+    let owned = TempChannel::owned_by(msg.author.id.0 as i64, &conn)
+        .context("failed to get the user's temporary channels")?;
+    if owned.len() >= config.temp_channel_limit {
+        msg.reply(
+            &ctx,
+            format!(
+                "You can only have {} temporary voice channels at once. Unused ones are deleted automatically.",
+                config.temp_channel_limit
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let reply = match create_temp_channel(ctx, &data, msg.author.id, args.rest().trim()).await {
+        Ok(channel) => format!("Created a temporary voice channel {:?}", channel.name),
+        Err(err) => format!("Failed to create a temporary voice channel: {}", err),
+    };
+    msg.reply(&ctx, &reply).await?;
+    Ok(())
+}
+
+/// Creates a temporary voice channel named `name` that belongs to `owner`.
+pub async fn create_temp_channel(
+    ctx: &Context,
+    data: &TypeMap,
+    owner: UserId,
+    name: &str,
+) -> Result<GuildChannel, anyhow::Error> {
+    let config = data.extract::<Config>()?;
+    let name = format!("{} {}", config.temp_channel_prefix, name);
+    let channel = config
         .guild
-        .create_channel(&ctx, |c| {
+        .create_channel(ctx, |c| {
             c.name(name).category(config.voice_category).kind(ChannelType::Voice)
         })
-        .await
+        .await?;
+
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a database connection from the pool")?;
+    let now = Utc::now();
+    TempChannel {
+        id: channel.id.0 as i64,
+        owner_id: owner.0 as i64,
+        created_at: now,
+        last_occupied_at: now,
+    }
+    .insert(&conn)
+    .context("failed to record the temporary channel")?;
+
+    Ok(channel)
+}
+
+/// The name of a channel created from the lobby for `user`, who's playing `game`, if anything.
+/// See `Config::voice_lobby_template` and `Config::voice_lobby_fallback`.
+pub fn lobby_channel_name(
+    template: &str,
+    fallback: &str,
+    user: &str,
+    game: Option<&str>,
+) -> Result<String, anyhow::Error> {
+    let mut vars = HashMap::new();
+    vars.insert(String::from("user"), user);
+    let template = match game {
+        Some(game) => {
+            vars.insert(String::from("game"), game);
+            template
+        }
+        None => fallback,
+    };
+    let name =
+        strfmt::strfmt(template, &vars).context("failed to fill in the channel name template")?;
+    // Leave room for the prefix in Discord's 100 character limit.
+    Ok(name.trim().chars().take(80).collect())
+}
+
+/// The temporary channel the author of `msg` wants to change: the one they're in if they own it,
+/// otherwise the only one they own.
+async fn owned_channel(ctx: &Context, msg: &Message) -> Result<Option<ChannelId>, anyhow::Error> {
+    let data = ctx.data.read().await;
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a database connection from the pool")?;
+    let owned = TempChannel::owned_by(msg.author.id.0 as i64, &conn)
+        .context("failed to get the user's temporary channels")?;
+
+    let current = match msg.guild(ctx).await {
+        Some(guild) => guild.voice_states.get(&msg.author.id).and_then(|state| state.channel_id),
+        None => None,
+    };
+    if let Some(current) = current {
+        if owned.iter().any(|channel| channel.id == current.0 as i64) {
+            return Ok(Some(current));
+        }
+    }
+
+    match &owned[..] {
+        [channel] => Ok(Some(ChannelId(channel.id as u64))),
+        _ => Ok(None),
+    }
+}
+
+async fn reply_not_owned(ctx: &Context, msg: &Message) -> CommandResult {
+    msg.reply(
+        ctx,
+        "You don't own a temporary voice channel. If you own several, join the one you want to change.",
+    )
+    .await?;
+    Ok(())
+}
+
+#[command]
+#[description = "Rename your temporary voice channel."]
+#[usage = "CHANNEL NAME"]
+#[example = "PUBG #16"]
+#[only_in(guilds)]
+#[min_args(1)]
+async fn rename(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let channel = match owned_channel(ctx, msg).await? {
+        Some(channel) => channel,
+        None => return reply_not_owned(ctx, msg).await,
+    };
+
+    let name = {
+        let data = ctx.data.read().await;
+        format!("{} {}", data.extract::<Config>()?.temp_channel_prefix, args.rest().trim())
+    };
+    channel.edit(ctx, |c| c.name(&name)).await?;
+    msg.reply(ctx, format!("Renamed the channel to {:?}", name)).await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Limit how many people can be in your temporary voice channel. 0 removes the limit."]
+#[usage = "LIMIT"]
+#[example = "4"]
+#[only_in(guilds)]
+#[num_args(1)]
+async fn limit(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let limit = match args.single::<u64>() {
+        Ok(limit) if limit <= 99 => limit,
+        _ => {
+            msg.reply(ctx, "The limit has to be a number from 0 to 99.").await?;
+            return Ok(());
+        }
+    };
+    let channel = match owned_channel(ctx, msg).await? {
+        Some(channel) => channel,
+        None => return reply_not_owned(ctx, msg).await,
+    };
+
+    channel.edit(ctx, |c| c.user_limit(limit)).await?;
+    if limit == 0 {
+        msg.reply(ctx, "Removed the user limit.").await?;
+    } else {
+        msg.reply(ctx, format!("Limited the channel to {} users.", limit)).await?;
+    }
+
+    Ok(())
+}
+
+#[command]
+#[description = "Stop anyone else from joining your temporary voice channel."]
+#[only_in(guilds)]
+#[num_args(0)]
+async fn lock(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    let channel = match owned_channel(ctx, msg).await? {
+        Some(channel) => channel,
+        None => return reply_not_owned(ctx, msg).await,
+    };
+    let guild = msg.guild_id.context("not in a guild")?;
+
+    channel
+        .create_permission(
+            ctx,
+            &PermissionOverwrite {
+                allow: Permissions::CONNECT,
+                deny: Permissions::empty(),
+                kind: PermissionOverwriteType::Member(msg.author.id),
+            },
+        )
+        .await?;
+    // The @everyone role shares its ID with the guild.
+    channel
+        .create_permission(
+            ctx,
+            &PermissionOverwrite {
+                allow: Permissions::empty(),
+                deny: Permissions::CONNECT,
+                kind: PermissionOverwriteType::Role(RoleId(guild.0)),
+            },
+        )
+        .await?;
+    msg.reply(ctx, "Locked the channel.").await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Let anyone join your temporary voice channel again."]
+#[only_in(guilds)]
+#[num_args(0)]
+async fn unlock(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    let channel = match owned_channel(ctx, msg).await? {
+        Some(channel) => channel,
+        None => return reply_not_owned(ctx, msg).await,
+    };
+    let guild = msg.guild_id.context("not in a guild")?;
+
+    channel.delete_permission(ctx, PermissionOverwriteType::Role(RoleId(guild.0))).await?;
+    msg.reply(ctx, "Unlocked the channel.").await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Give your temporary voice channel to someone else."]
+#[usage = "@USER"]
+#[only_in(guilds)]
+#[num_args(1)]
+async fn transfer(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    let new_owner = match msg.mentions.first() {
+        Some(user) if !user.bot => user,
+        _ => {
+            msg.reply(ctx, "Mention who to give the channel to.").await?;
+            return Ok(());
+        }
+    };
+    let channel = match owned_channel(ctx, msg).await? {
+        Some(channel) => channel,
+        None => return reply_not_owned(ctx, msg).await,
+    };
+
     {
-        Ok(channel) => format!("Created a temporary voice channel {:?}", channel.name),
-        Err(err) => format!("Failed to create a temporary voice channel: {}", err),
+        let data = ctx.data.read().await;
+        let config = data.extract::<Config>()?;
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+
+        let owned = TempChannel::owned_by(new_owner.id.0 as i64, &conn)
+            .context("failed to get the user's temporary channels")?;
+        if owned.len() >= config.temp_channel_limit {
+            msg.reply(
+                ctx,
+                MessageBuilder::new()
+                    .push_safe(&new_owner.name)
+                    .push(" already has as many temporary voice channels as they can.")
+                    .build(),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        TempChannel::set_owner(channel.0 as i64, new_owner.id.0 as i64, &conn)
+            .context("failed to change the owner of the temporary channel")?;
+    }
+
+    // Lets the new owner in if the channel is locked.
+    channel
+        .create_permission(
+            ctx,
+            &PermissionOverwrite {
+                allow: Permissions::CONNECT,
+                deny: Permissions::empty(),
+                kind: PermissionOverwriteType::Member(new_owner.id),
+            },
+        )
+        .await?;
+    // And stops letting the old one in.
+    let old_owner = PermissionOverwriteType::Member(msg.author.id);
+    let has_overwrite = match channel.to_channel_cached(ctx).await {
+        Some(Channel::Guild(channel)) => {
+            channel.permission_overwrites.iter().any(|overwrite| overwrite.kind == old_owner)
+        }
+        _ => true,
     };
-    msg.reply(&ctx, &reply).await?;
+    if new_owner.id != msg.author.id && has_overwrite {
+        channel.delete_permission(ctx, old_owner).await?;
+    }
+    msg.reply(ctx, MessageBuilder::new().push("Gave the channel to ").mention(new_owner).build())
+        .await?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::lobby_channel_name;
+
+    #[test]
+    fn lobby_names() {
+        let name = |game| lobby_channel_name("{game} with {user}", "{user}'s channel", "Ian", game);
+        assert_eq!(name(Some("Hades")).unwrap(), "Hades with Ian");
+        assert_eq!(name(None).unwrap(), "Ian's channel");
+        assert_eq!(name(Some(&"a".repeat(100))).unwrap().len(), 80);
+    }
+}
@@ -1,8 +1,11 @@
 #![allow(clippy::unreadable_literal)]
 
+use crate::desertbus::DesertBusSettings;
+use crate::time::parse_duration;
 use anyhow::{anyhow, Context, Error};
 use chrono_tz::Tz;
 use ini::Ini;
+use regex::Regex;
 use serenity::model::prelude::*;
 use std::collections::HashMap;
 use std::error::Error as StdError;
@@ -10,6 +13,249 @@ use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use url::Url;
 
+/// Which replies the tweet announcer posts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Replies {
+    All,
+    /// Only replies to accounts in `Config::twitter_users`.
+    Watched,
+    None,
+}
+
+impl FromStr for Replies {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Replies, Error> {
+        match s {
+            "all" => Ok(Replies::All),
+            "watched" => Ok(Replies::Watched),
+            "none" => Ok(Replies::None),
+            _ => Err(anyhow!("expected one of `all`, `watched` or `none`, got {:?}", s)),
+        }
+    }
+}
+
+/// Per-account rules for which tweets get announced and how, from the `[eris.twitter.NAME]`
+/// sections.
+#[derive(Debug)]
+pub struct TweetRules {
+    /// The tweet's text has to match this, if set.
+    pub include: Option<Regex>,
+    /// The tweet's text must not match this, if set.
+    pub exclude: Option<Regex>,
+    pub replies: Replies,
+    /// Whether to post tweets that start with a user mention.
+    pub mentions: bool,
+    pub retweets: bool,
+    pub quote_tweets: bool,
+    /// Announcement message with `{name}`, `{screen_name}`, `{id}`, `{url}` and `{text}`
+    /// placeholders.
+    pub template: String,
+}
+
+impl Default for TweetRules {
+    fn default() -> TweetRules {
+        TweetRules {
+            include: None,
+            exclude: None,
+            replies: Replies::Watched,
+            mentions: false,
+            retweets: true,
+            quote_tweets: true,
+            template: String::from("New tweet from {name}: {url}"),
+        }
+    }
+}
+
+impl TweetRules {
+    fn from_section(properties: &ini::Properties) -> Result<TweetRules, Error> {
+        fn parse_bool(value: &str) -> Result<bool, Error> {
+            match value {
+                "true" | "yes" | "on" | "1" => Ok(true),
+                "false" | "no" | "off" | "0" => Ok(false),
+                _ => Err(anyhow!("expected a boolean, got {:?}", value)),
+            }
+        }
+
+        let defaults = TweetRules::default();
+        let rules = TweetRules {
+            include: properties
+                .get("include")
+                .map(Regex::new)
+                .transpose()
+                .context("failed to parse `include`")?,
+            exclude: properties
+                .get("exclude")
+                .map(Regex::new)
+                .transpose()
+                .context("failed to parse `exclude`")?,
+            replies: properties
+                .get("replies")
+                .map(str::parse)
+                .transpose()
+                .context("failed to parse `replies`")?
+                .unwrap_or(defaults.replies),
+            mentions: properties
+                .get("mentions")
+                .map(parse_bool)
+                .transpose()
+                .context("failed to parse `mentions`")?
+                .unwrap_or(defaults.mentions),
+            retweets: properties
+                .get("retweets")
+                .map(parse_bool)
+                .transpose()
+                .context("failed to parse `retweets`")?
+                .unwrap_or(defaults.retweets),
+            quote_tweets: properties
+                .get("quote_tweets")
+                .map(parse_bool)
+                .transpose()
+                .context("failed to parse `quote_tweets`")?
+                .unwrap_or(defaults.quote_tweets),
+            template: properties.get("template").map(String::from).unwrap_or(defaults.template),
+        };
+
+        check_template(&rules.template, &["name", "screen_name", "id", "url", "text"])
+            .context("failed to parse `template`")?;
+
+        Ok(rules)
+    }
+}
+
+/// Checks that `template` only uses the placeholders in `vars`, to catch typos at startup rather
+/// than when the template is first used.
+fn check_template(template: &str, vars: &[&str]) -> Result<(), Error> {
+    let vars = vars.iter().map(|&var| (String::from(var), "")).collect::<HashMap<String, &str>>();
+    strfmt::strfmt(template, &vars)?;
+    Ok(())
+}
+
+/// Channel topic templates. The defaults come from the `[eris.autotopic]` section and can be
+/// overridden per channel in the `[eris.autotopic.NAME]` sections.
+#[derive(Debug, Clone)]
+pub struct AutotopicTemplates {
+    /// When live and the game or the show is known. Variables: `{game}`, `{show}`, `{title}`
+    /// (the game on the show, or whichever is known), `{started}` and `{uptime}`.
+    pub live: String,
+    /// When live but neither the game nor the show is known. Variables: `{started}` and
+    /// `{uptime}`.
+    pub live_unknown: String,
+    /// When offline. Variables: `{next_events}`.
+    pub offline: String,
+    /// Before Desert Bus starts. Variables: `{next_events}`, `{total}` and `{hours}`.
+    pub desertbus_upcoming: String,
+    /// During Desert Bus. Variables: `{total}`, `{hours}` and `{bussed}`.
+    pub desertbus_live: String,
+    /// When the Desert Bus total can't be fetched.
+    pub desertbus_unknown: String,
+    /// The dynamic tail of the topic. Variables: `{advice}`.
+    pub advice: String,
+    /// When fanstreamers are live. Variables: `{streams}`.
+    pub fanstreams_live: String,
+    /// When no fanstreamers are live. Variables: `{next_events}`.
+    pub fanstreams_offline: String,
+}
+
+impl Default for AutotopicTemplates {
+    fn default() -> AutotopicTemplates {
+        AutotopicTemplates {
+            live: String::from("Now live: {title}. {uptime}"),
+            live_unknown: String::from("Now live: something? {uptime}"),
+            offline: String::from("{next_events}"),
+            desertbus_upcoming: String::from("{next_events} ${total} raised."),
+            desertbus_live: String::from(
+                "DESERT BUS! (https://desertbus.org/ or https://twitch.tv/desertbus) ${total} raised. {bussed} hours of {hours} so far.",
+            ),
+            desertbus_unknown: String::from("DESERT BUS?"),
+            advice: String::from("{advice}"),
+            fanstreams_live: String::from("Currently live fanstreamers: {streams}"),
+            fanstreams_offline: String::from("{next_events}"),
+        }
+    }
+}
+
+impl AutotopicTemplates {
+    fn from_section(
+        defaults: &AutotopicTemplates,
+        properties: Option<&ini::Properties>,
+    ) -> Result<AutotopicTemplates, Error> {
+        let mut templates = defaults.clone();
+
+        for (key, template, vars) in [
+            ("live", &mut templates.live, &["game", "show", "title", "started", "uptime"][..]),
+            ("live_unknown", &mut templates.live_unknown, &["started", "uptime"][..]),
+            ("offline", &mut templates.offline, &["next_events"][..]),
+            (
+                "desertbus_upcoming",
+                &mut templates.desertbus_upcoming,
+                &["next_events", "total", "hours"][..],
+            ),
+            ("desertbus_live", &mut templates.desertbus_live, &["total", "hours", "bussed"][..]),
+            ("desertbus_unknown", &mut templates.desertbus_unknown, &[][..]),
+            ("advice", &mut templates.advice, &["advice"][..]),
+            ("fanstreams_live", &mut templates.fanstreams_live, &["streams"][..]),
+            ("fanstreams_offline", &mut templates.fanstreams_offline, &["next_events"][..]),
+        ] {
+            if let Some(value) = properties.and_then(|properties| properties.get(key)) {
+                *template = String::from(value);
+            }
+            check_template(template, vars).with_context(|| format!("failed to parse `{}`", key))?;
+        }
+
+        Ok(templates)
+    }
+}
+
+/// What to do with people who join the guild's AFK channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AfkPolicy {
+    /// Disconnect them from voice.
+    Kick,
+    /// Move them to another voice channel.
+    Move(ChannelId),
+    Ignore,
+}
+
+/// What a channel's topic is about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TopicSource {
+    /// The LRR stream and the LRR calendar.
+    Lrr,
+    /// Live fanstreamers and the fanstreams calendar.
+    Fanstreams,
+    DesertBus,
+}
+
+impl FromStr for TopicSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<TopicSource, Error> {
+        match s {
+            "lrr" => Ok(TopicSource::Lrr),
+            "fanstreams" => Ok(TopicSource::Fanstreams),
+            "desertbus" => Ok(TopicSource::DesertBus),
+            _ => Err(anyhow!("expected one of `lrr`, `fanstreams` or `desertbus`, got {:?}", s)),
+        }
+    }
+}
+
+/// A channel whose topic is managed by autotopic.
+#[derive(Debug, Clone)]
+pub struct TopicConfig {
+    pub name: String,
+    pub channel: ChannelId,
+    pub source: TopicSource,
+    pub templates: AutotopicTemplates,
+}
+
+#[derive(Debug)]
+pub struct FeedConfig {
+    pub name: String,
+    pub url: Url,
+    pub channels: Vec<ChannelId>,
+}
+
 #[derive(Debug)]
 pub struct Config {
     pub username: String,
@@ -43,6 +289,26 @@ pub struct Config {
 
     pub discord_botsecret: String,
     pub temp_channel_prefix: String,
+    /// How many temporary voice channels a user can own at once.
+    pub temp_channel_limit: usize,
+    /// How long temporary channels can stay empty before they're deleted.
+    pub temp_channel_idle_timeout: chrono::Duration,
+    /// Overrides `temp_channel_idle_timeout` for channels in these categories.
+    pub temp_channel_category_timeouts: HashMap<ChannelId, chrono::Duration>,
+    /// What to do with people in the AFK channel, from `[eris].afk_policy` and
+    /// `[eris].afk_move_channel`.
+    pub afk_policy: AfkPolicy,
+    /// Members with any of these roles are left alone in the AFK channel.
+    pub afk_exempt_roles: Vec<RoleId>,
+    /// Where `!tempchannel` creates channels. Defaults to the category the command was used in.
+    pub temp_text_category: Option<ChannelId>,
+    /// Joining this voice channel creates a temporary voice channel and moves the user into it.
+    pub voice_lobby: Option<ChannelId>,
+    /// The name of channels created from the lobby. Variables: `{user}` and `{game}`.
+    pub voice_lobby_template: String,
+    /// The name of channels created from the lobby when the user isn't playing anything.
+    /// Variables: `{user}`.
+    pub voice_lobby_fallback: String,
     pub announcements: ChannelId,
     pub voice_category: ChannelId,
     pub mods_channel: ChannelId,
@@ -52,9 +318,45 @@ pub struct Config {
     pub twitter_api_key: String,
     pub twitter_api_secret: String,
     pub twitter_users: HashMap<String, Vec<ChannelId>>,
+    /// Keyed by the lowercase screen name. Accounts without rules use `TweetRules::default()`.
+    pub twitter_rules: HashMap<String, TweetRules>,
+
+    /// Mastodon accounts to announce, keyed by `user@instance`.
+    pub mastodon_users: HashMap<String, Vec<ChannelId>>,
+    /// Overrides the account's own instance as the Mastodon API endpoint.
+    pub mastodon_api_base: Option<Url>,
+
+    /// RSS and Atom feeds to announce, from the `[eris.feed.NAME]` sections.
+    pub feeds: Vec<FeedConfig>,
 
     pub contact_spreadsheet: Option<String>,
 
+    /// Roles mentioned in the stream up announcements, keyed by the show's string ID.
+    pub show_roles: HashMap<String, RoleId>,
+    /// Role mentioned in every stream up announcement.
+    pub stream_up_role: Option<RoleId>,
+
+    /// Streams that come back within this long of going offline aren't announced again.
+    pub stream_up_grace_period: chrono::Duration,
+
+    /// How long before a scheduled stream its reminder is posted to `announcements`.
+    pub stream_reminder: chrono::Duration,
+
+    /// Channels with automatic topics. Defaults to the LRR topic in `general_channel`.
+    pub topics: Vec<TopicConfig>,
+
+    /// Where changes to the LRR calendar are posted, if anywhere.
+    pub schedule_changes_channel: Option<ChannelId>,
+
+    /// Desert Bus defaults from `[eris.desertbus]`, which can be overridden at runtime.
+    pub desertbus: DesertBusSettings,
+
+    /// Where Desert Bus milestones, new hours and shift changes are posted, if anywhere.
+    pub desertbus_channel: Option<ChannelId>,
+
+    /// Totals worth announcing when crossed, in ascending order.
+    pub desertbus_milestones: Vec<f64>,
+
     /// URL for the InfluxDB's write endpoint.
     pub influxdb: Option<Url>,
 }
@@ -62,6 +364,14 @@ pub struct Config {
 impl Config {
     pub fn load_from_file<P: AsRef<Path>>(filename: P) -> Result<Config, Error> {
         let ini = Ini::load_from_file(filename)?;
+        let reaper_timeouts = Config::get_reaper_timeouts(&ini)?;
+        let general_channel = ChannelId(
+            if let Some(channel_id) = Config::get_option_parsed(&ini, "discord_channel_general")? {
+                channel_id
+            } else {
+                Config::get_option_parsed(&ini, "discord_serverid")?.unwrap_or(288920509272555520)
+            },
+        );
         Ok(Config {
             username: ini.get_from(Some("lrrbot"), "username").unwrap_or("lrrbot").into(),
             channel: ini.get_from(Some("lrrbot"), "channel").unwrap_or("loadingreadyrun").into(),
@@ -120,6 +430,54 @@ impl Config {
                 .unwrap_or("[TEMP]")
                 .trim()
                 .into(),
+            temp_channel_limit: ini
+                .get_from(Some("eris"), "temp_channel_limit")
+                .map(str::parse)
+                .transpose()
+                .context("failed to parse `[eris].temp_channel_limit`")?
+                .unwrap_or(2),
+            temp_channel_idle_timeout: reaper_timeouts.0,
+            temp_channel_category_timeouts: reaper_timeouts.1,
+            afk_policy: Config::get_afk_policy(&ini)?,
+            afk_exempt_roles: ini
+                .get_from(Some("eris"), "afk_exempt_roles")
+                .map(|roles| {
+                    roles.split(',').map(|role| role.trim().parse().map(RoleId)).collect::<Result<
+                        Vec<RoleId>,
+                        _,
+                    >>(
+                    )
+                })
+                .transpose()
+                .context("failed to parse `[eris].afk_exempt_roles`")?
+                .unwrap_or_default(),
+            temp_text_category: ini
+                .get_from(Some("eris"), "temp_text_category")
+                .map(str::parse)
+                .transpose()
+                .context("failed to parse `[eris].temp_text_category`")?
+                .map(ChannelId),
+            voice_lobby: ini
+                .get_from(Some("eris"), "voice_lobby")
+                .map(str::parse)
+                .transpose()
+                .context("failed to parse `[eris].voice_lobby`")?
+                .map(ChannelId),
+            voice_lobby_template: {
+                let template =
+                    ini.get_from(Some("eris"), "voice_lobby_template").unwrap_or("{game}");
+                check_template(template, &["user", "game"])
+                    .context("failed to parse `[eris].voice_lobby_template`")?;
+                template.into()
+            },
+            voice_lobby_fallback: {
+                let template = ini
+                    .get_from(Some("eris"), "voice_lobby_fallback")
+                    .unwrap_or("{user}'s channel");
+                check_template(template, &["user"])
+                    .context("failed to parse `[eris].voice_lobby_fallback`")?;
+                template.into()
+            },
             announcements: ChannelId(
                 Config::get_option_parsed(&ini, "discord_channel_announcements")?
                     .unwrap_or(322643668831961088),
@@ -132,16 +490,7 @@ impl Config {
                 Config::get_option_parsed(&ini, "discord_channel_mods")?
                     .unwrap_or(289166968307712000),
             ),
-            general_channel: ChannelId(
-                if let Some(channel_id) =
-                    Config::get_option_parsed(&ini, "discord_channel_general")?
-                {
-                    channel_id
-                } else {
-                    Config::get_option_parsed(&ini, "discord_serverid")?
-                        .unwrap_or(288920509272555520)
-                },
-            ),
+            general_channel,
             guild: GuildId(
                 Config::get_option_parsed(&ini, "discord_serverid")?.unwrap_or(288920509272555520),
             ),
@@ -173,10 +522,144 @@ impl Config {
                     Ok(twitter)
                 })?,
 
+            twitter_rules: ini
+                .iter()
+                .filter_map(|(section, properties)| {
+                    Some((section?.strip_prefix("eris.twitter.")?, properties))
+                })
+                .map(|(name, properties)| {
+                    Ok((
+                        name.to_lowercase(),
+                        TweetRules::from_section(properties).with_context(|| {
+                            format!("failed to parse `[eris.twitter.{}]`", name)
+                        })?,
+                    ))
+                })
+                .collect::<Result<HashMap<String, TweetRules>, Error>>()?,
+
+            mastodon_users: ini
+                .section(Some("eris.mastodon"))
+                .map(|section| {
+                    section
+                        .iter()
+                        .map(|(acct, channels)| {
+                            Ok((
+                                acct.to_lowercase(),
+                                channels
+                                    .split(',')
+                                    .map(|id| Ok(ChannelId(str::parse(id.trim())?)))
+                                    .collect::<Result<Vec<ChannelId>, Error>>()?,
+                            ))
+                        })
+                        .collect::<Result<HashMap<String, Vec<ChannelId>>, Error>>()
+                })
+                .transpose()
+                .context("failed to parse `[eris.mastodon]`")?
+                .unwrap_or_default(),
+            mastodon_api_base: ini
+                .get_from(Some("eris"), "mastodon_api_base")
+                .map(Url::parse)
+                .transpose()
+                .context("failed to parse `[eris].mastodon_api_base`")?,
+
+            feeds: ini
+                .iter()
+                .filter_map(|(section, properties)| {
+                    Some((section?.strip_prefix("eris.feed.")?, properties))
+                })
+                .map(|(name, properties)| {
+                    let parse = || -> Result<FeedConfig, Error> {
+                        let url =
+                            properties.get("url").ok_or_else(|| anyhow!("`url` is missing"))?;
+                        Ok(FeedConfig {
+                            name: name.into(),
+                            url: Url::parse(url)?,
+                            channels: properties
+                                .get("channels")
+                                .unwrap_or_default()
+                                .split(',')
+                                .map(str::trim)
+                                .filter(|id| !id.is_empty())
+                                .map(|id| Ok(ChannelId(id.parse()?)))
+                                .collect::<Result<Vec<ChannelId>, Error>>()?,
+                        })
+                    };
+                    parse().with_context(|| format!("failed to parse `[eris.feed.{}]`", name))
+                })
+                .collect::<Result<Vec<FeedConfig>, Error>>()?,
+
             contact_spreadsheet: ini
                 .get_from(Some("lrrbot"), "discord_contact_spreadsheet")
                 .map(String::from),
 
+            show_roles: ini
+                .section(Some("eris.show_roles"))
+                .map(|section| {
+                    section
+                        .iter()
+                        .map(|(show, role)| Ok((show.to_lowercase(), RoleId(str::parse(role)?))))
+                        .collect::<Result<HashMap<String, RoleId>, Error>>()
+                })
+                .transpose()
+                .context("failed to parse `[eris.show_roles]`")?
+                .unwrap_or_default(),
+            stream_up_role: ini
+                .get_from(Some("eris"), "stream_up_role")
+                .map(str::parse)
+                .transpose()
+                .context("failed to parse `[eris].stream_up_role`")?
+                .map(RoleId),
+
+            stream_up_grace_period: chrono::Duration::minutes(
+                ini.get_from(Some("eris"), "stream_up_grace_period")
+                    .map(str::parse)
+                    .transpose()
+                    .context("failed to parse `[eris].stream_up_grace_period`")?
+                    .unwrap_or(15),
+            ),
+
+            stream_reminder: chrono::Duration::minutes(
+                ini.get_from(Some("eris"), "stream_reminder")
+                    .map(str::parse)
+                    .transpose()
+                    .context("failed to parse `[eris].stream_reminder`")?
+                    .unwrap_or(15),
+            ),
+
+            topics: Config::get_topics(&ini, general_channel)?,
+
+            schedule_changes_channel: ini
+                .get_from(Some("eris"), "schedule_changes_channel")
+                .map(str::parse)
+                .transpose()
+                .context("failed to parse `[eris].schedule_changes_channel`")?
+                .map(ChannelId),
+
+            desertbus: Config::get_desertbus(&ini)?,
+
+            desertbus_channel: ini
+                .get_from(Some("eris"), "desertbus_channel")
+                .map(str::parse)
+                .transpose()
+                .context("failed to parse `[eris].desertbus_channel`")?
+                .map(ChannelId),
+
+            desertbus_milestones: {
+                let mut milestones = ini
+                    .get_from(Some("eris"), "desertbus_milestones")
+                    .map(|milestones| {
+                        milestones
+                            .split(',')
+                            .map(|milestone| milestone.trim().replace('_', "").parse::<f64>())
+                            .collect::<Result<Vec<f64>, _>>()
+                    })
+                    .transpose()
+                    .context("failed to parse `[eris].desertbus_milestones`")?
+                    .unwrap_or_default();
+                milestones.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                milestones
+            },
+
             influxdb: ini
                 .get_from(Some("eris"), "influxdb")
                 .map(Url::parse)
@@ -185,6 +668,107 @@ impl Config {
         })
     }
 
+    fn get_topics(ini: &Ini, general_channel: ChannelId) -> Result<Vec<TopicConfig>, Error> {
+        let templates = AutotopicTemplates::from_section(
+            &Default::default(),
+            ini.section(Some("eris.autotopic")),
+        )
+        .context("failed to parse `[eris.autotopic]`")?;
+
+        let topics = ini
+            .iter()
+            .filter_map(|(section, properties)| {
+                Some((section?.strip_prefix("eris.autotopic.")?, properties))
+            })
+            .map(|(name, properties)| {
+                let parse = || -> Result<TopicConfig, Error> {
+                    let channel = properties
+                        .get("channel")
+                        .ok_or_else(|| anyhow!("`channel` is missing"))?
+                        .parse()
+                        .context("failed to parse `channel`")?;
+                    Ok(TopicConfig {
+                        name: name.into(),
+                        channel: ChannelId(channel),
+                        source: properties
+                            .get("source")
+                            .ok_or_else(|| anyhow!("`source` is missing"))?
+                            .parse()
+                            .context("failed to parse `source`")?,
+                        templates: AutotopicTemplates::from_section(&templates, Some(properties))?,
+                    })
+                };
+                parse().with_context(|| format!("failed to parse `[eris.autotopic.{}]`", name))
+            })
+            .collect::<Result<Vec<TopicConfig>, Error>>()?;
+
+        if topics.is_empty() {
+            Ok(vec![TopicConfig {
+                name: String::from("general"),
+                channel: general_channel,
+                source: TopicSource::Lrr,
+                templates,
+            }])
+        } else {
+            Ok(topics)
+        }
+    }
+
+    fn get_afk_policy(ini: &Ini) -> Result<AfkPolicy, Error> {
+        match ini.get_from(Some("eris"), "afk_policy").unwrap_or("kick") {
+            "kick" => Ok(AfkPolicy::Kick),
+            "move" => Ok(AfkPolicy::Move(ChannelId(
+                ini.get_from(Some("eris"), "afk_move_channel")
+                    .ok_or_else(|| anyhow!("`[eris].afk_move_channel` is missing"))?
+                    .parse()
+                    .context("failed to parse `[eris].afk_move_channel`")?,
+            ))),
+            "ignore" => Ok(AfkPolicy::Ignore),
+            policy => Err(anyhow!(
+                "failed to parse `[eris].afk_policy`: expected one of `kick`, `move` or `ignore`, got {:?}",
+                policy
+            )),
+        }
+    }
+
+    /// The idle timeouts from `[eris.channel_reaper]`: `idle_timeout` for the default and category
+    /// IDs for the overrides, all durations like `15m`.
+    fn get_reaper_timeouts(
+        ini: &Ini,
+    ) -> Result<(chrono::Duration, HashMap<ChannelId, chrono::Duration>), Error> {
+        let mut default = chrono::Duration::minutes(15);
+        let mut categories = HashMap::new();
+
+        for (key, value) in
+            ini.section(Some("eris.channel_reaper")).into_iter().flat_map(|p| p.iter())
+        {
+            let timeout = parse_duration(value)
+                .ok_or_else(|| anyhow!("expected a duration like `15m`, got {:?}", value))
+                .with_context(|| format!("failed to parse `[eris.channel_reaper].{}`", key))?;
+            if key == "idle_timeout" {
+                default = timeout;
+            } else {
+                let category = key
+                    .parse()
+                    .with_context(|| format!("expected a category ID, got {:?}", key))
+                    .context("failed to parse `[eris.channel_reaper]`")?;
+                categories.insert(ChannelId(category), timeout);
+            }
+        }
+
+        Ok((default, categories))
+    }
+
+    fn get_desertbus(ini: &Ini) -> Result<DesertBusSettings, Error> {
+        let mut settings = DesertBusSettings::default();
+        for (key, value) in ini.section(Some("eris.desertbus")).into_iter().flat_map(|p| p.iter()) {
+            settings
+                .set(key, value)
+                .with_context(|| format!("failed to parse `[eris.desertbus].{}`", key))?;
+        }
+        Ok(settings)
+    }
+
     fn get_option_required(ini: &Ini, option: &str) -> Result<String, Error> {
         Ok(ini
             .get_from(Some("lrrbot"), option)
@@ -206,3 +790,80 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AfkPolicy, Config, TopicSource};
+    use chrono::Duration;
+    use ini::Ini;
+    use serenity::model::id::ChannelId;
+
+    #[test]
+    fn afk_policy() {
+        assert_eq!(Config::get_afk_policy(&Ini::new()).unwrap(), AfkPolicy::Kick);
+
+        let ini = Ini::load_from_str("[eris]\nafk_policy = move\nafk_move_channel = 5\n").unwrap();
+        assert_eq!(Config::get_afk_policy(&ini).unwrap(), AfkPolicy::Move(ChannelId(5)));
+
+        let ini = Ini::load_from_str("[eris]\nafk_policy = move\n").unwrap();
+        assert!(Config::get_afk_policy(&ini).is_err());
+        let ini = Ini::load_from_str("[eris]\nafk_policy = ban\n").unwrap();
+        assert!(Config::get_afk_policy(&ini).is_err());
+    }
+
+    #[test]
+    fn reaper_timeouts() {
+        let (default, categories) = Config::get_reaper_timeouts(&Ini::new()).unwrap();
+        assert_eq!(default, Duration::minutes(15));
+        assert!(categories.is_empty());
+
+        let ini = Ini::load_from_str(
+            "[eris.channel_reaper]\nidle_timeout = 5m\n360796352357072896 = 1h\n",
+        )
+        .unwrap();
+        let (default, categories) = Config::get_reaper_timeouts(&ini).unwrap();
+        assert_eq!(default, Duration::minutes(5));
+        assert_eq!(categories[&ChannelId(360796352357072896)], Duration::hours(1));
+
+        let ini = Ini::load_from_str("[eris.channel_reaper]\nvoice = 1h\n").unwrap();
+        assert!(Config::get_reaper_timeouts(&ini).is_err());
+    }
+
+    #[test]
+    fn default_topics() {
+        let ini =
+            Ini::load_from_str("[eris.autotopic]\noffline = Nothing scheduled. {next_events}\n")
+                .unwrap();
+        let topics = Config::get_topics(&ini, ChannelId(1)).unwrap();
+        assert_eq!(topics.len(), 1);
+        assert_eq!(topics[0].channel, ChannelId(1));
+        assert_eq!(topics[0].source, TopicSource::Lrr);
+        assert_eq!(topics[0].templates.offline, "Nothing scheduled. {next_events}");
+    }
+
+    #[test]
+    fn topics() {
+        let ini = Ini::load_from_str(
+            "[eris.autotopic]\n\
+             offline = Nothing scheduled. {next_events}\n\
+             [eris.autotopic.fanstreams]\n\
+             channel = 2\n\
+             source = fanstreams\n\
+             fanstreams_live = Live: {streams}\n",
+        )
+        .unwrap();
+        let topics = Config::get_topics(&ini, ChannelId(1)).unwrap();
+        assert_eq!(topics.len(), 1);
+        assert_eq!(topics[0].name, "fanstreams");
+        assert_eq!(topics[0].channel, ChannelId(2));
+        assert_eq!(topics[0].source, TopicSource::Fanstreams);
+        assert_eq!(topics[0].templates.offline, "Nothing scheduled. {next_events}");
+        assert_eq!(topics[0].templates.fanstreams_live, "Live: {streams}");
+
+        let ini = Ini::load_from_str(
+            "[eris.autotopic.general]\nchannel = 1\nsource = lrr\nlive = {typo}\n",
+        )
+        .unwrap();
+        assert!(Config::get_topics(&ini, ChannelId(1)).is_err());
+    }
+}
@@ -2,15 +2,143 @@ use crate::config::Config;
 use crate::context::ErisContext;
 use crate::extract::Extract;
 use crate::google::sheets::{CellData, ExtendedValue, Sheets, Spreadsheet};
+use crate::models::State;
 use crate::shorten::{shorten, split_to_parts};
+use crate::typemap_keys::PgPool;
 use anyhow::{Context, Error};
 use chrono::TimeZone;
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::TypeMap;
+use std::fmt;
+use std::str::FromStr;
 use std::time::Duration;
 use tracing::{error, info};
 
 const SENT_KEY: &str = "lrrbot.sent";
 
+/// The headers of the columns replies and the status are written to, compared case-insensitively.
+const REPLY_HEADERS: &[&str] = &["reply", "replies"];
+const STATUS_HEADERS: &[&str] = &["status"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Open,
+    Answered,
+    Closed,
+}
+
+impl FromStr for Status {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Status, Error> {
+        match s.to_lowercase().as_str() {
+            "open" => Ok(Status::Open),
+            "answered" => Ok(Status::Answered),
+            "closed" => Ok(Status::Closed),
+            _ => Err(Error::msg("the status has to be `open`, `answered` or `closed`")),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Open => f.write_str("open"),
+            Status::Answered => f.write_str("answered"),
+            Status::Closed => f.write_str("closed"),
+        }
+    }
+}
+
+/// Where replies and the status go in the spreadsheet, found by the headers in its first row.
+/// Columns that aren't there aren't written to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Columns {
+    pub reply: Option<u64>,
+    pub status: Option<u64>,
+}
+
+/// A thread in the mods channel about a message from the contact form.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContactThread {
+    pub thread: ChannelId,
+    pub sheet_id: u64,
+    pub row: u64,
+    /// What the sender put in the username field, if anything.
+    pub username: Option<String>,
+    pub status: Status,
+    pub replies: Vec<String>,
+    #[serde(default)]
+    pub columns: Columns,
+}
+
+impl ContactThread {
+    fn state_key(thread: ChannelId) -> String {
+        format!("eris.contact.threads.{}", thread.0)
+    }
+
+    pub fn find(data: &TypeMap, thread: ChannelId) -> Result<Option<ContactThread>, Error> {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+        State::get(&ContactThread::state_key(thread), &conn)
+            .context("failed to load the contact form thread")
+    }
+
+    /// Saves the thread, or forgets about it once it's closed.
+    pub fn save(&self, data: &TypeMap) -> Result<(), Error> {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+        let key = ContactThread::state_key(self.thread);
+        if self.status == Status::Closed {
+            State::delete(&key, &conn).context("failed to forget the contact form thread")
+        } else {
+            State::set(&key, self, &conn).context("failed to save the contact form thread")
+        }
+    }
+
+    /// Writes the replies and the status to the message's row in the spreadsheet.
+    pub async fn update_spreadsheet(&self, data: &TypeMap) -> Result<(), Error> {
+        let spreadsheet_key = data
+            .extract::<Config>()?
+            .contact_spreadsheet
+            .as_deref()
+            .ok_or_else(|| Error::msg("Contact spreadsheet is not set"))?;
+        let replies = self.replies.join("\n\n");
+        let status = self.status.to_string();
+        let sheets = data.extract::<Sheets>()?;
+        for (column, value) in [(self.columns.reply, &replies), (self.columns.status, &status)] {
+            if let Some(column) = column {
+                sheets
+                    .update_row_cells(spreadsheet_key, self.sheet_id, self.row, column, &[value])
+                    .await
+                    .context("failed to update the spreadsheet")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `user` is who the sender of a message said they are, either `name#1234` or just `name`.
+pub fn matches_username(user: &User, username: &str) -> bool {
+    let username = username.trim().trim_start_matches('@');
+    match username.rsplit_once('#') {
+        Some((name, discriminator))
+            if discriminator.len() == 4 && discriminator.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            user.name.eq_ignore_ascii_case(name.trim())
+                && discriminator.parse() == Ok(user.discriminator)
+        }
+        _ => user.name.eq_ignore_ascii_case(username),
+    }
+}
+
 pub async fn post_messages(ctx: ErisContext) {
     let spreadsheet_set = ctx
         .data
@@ -60,11 +188,26 @@ fn extract_string(cell: &CellData) -> Option<&str> {
     }
 }
 
-fn find_unsent_rows(spreadsheet: &Spreadsheet) -> Option<(u64, Vec<Entry>)> {
+fn find_columns(header: &[CellData], start_column: u64) -> Columns {
+    let find = |names: &[&str]| {
+        header.iter().position(|cell| {
+            extract_string(cell)
+                .map(|header| names.iter().any(|name| header.trim().eq_ignore_ascii_case(name)))
+                .unwrap_or(false)
+        })
+    };
+    Columns {
+        reply: find(REPLY_HEADERS).map(|i| start_column + i as u64),
+        status: find(STATUS_HEADERS).map(|i| start_column + i as u64),
+    }
+}
+
+fn find_unsent_rows(spreadsheet: &Spreadsheet) -> Option<(u64, Columns, Vec<Entry>)> {
     let sheets = spreadsheet.sheets.as_ref()?;
     let sheet = sheets.get(0)?;
     let sheet_id = sheet.properties.as_ref()?.sheet_id?;
 
+    let mut columns = Columns::default();
     let mut rows = vec![];
 
     for grid in sheet.data.as_ref()? {
@@ -75,6 +218,9 @@ fn find_unsent_rows(spreadsheet: &Spreadsheet) -> Option<(u64, Vec<Entry>)> {
         'row: for (i, (row, meta)) in row_data.zip(metadata).enumerate() {
             let row_idx = start_row + i as u64;
             if row_idx == 0 {
+                if let Some(header) = row.values.as_ref() {
+                    columns = find_columns(header, grid.start_column.unwrap_or(0));
+                }
                 continue;
             }
 
@@ -103,7 +249,48 @@ fn find_unsent_rows(spreadsheet: &Spreadsheet) -> Option<(u64, Vec<Entry>)> {
         }
     }
 
-    Some((sheet_id, rows))
+    Some((sheet_id, columns, rows))
+}
+
+async fn start_thread(
+    ctx: &ErisContext,
+    data: &TypeMap,
+    sheet_id: u64,
+    columns: Columns,
+    message: &Entry<'_>,
+    first: MessageId,
+) -> Result<(), Error> {
+    let name = match message.username {
+        Some(user) => format!("Contact form: {}", user),
+        None => format!("Contact form: {}", message.timestamp.format("%Y-%m-%d %H:%M")),
+    };
+    let thread = data
+        .extract::<Config>()?
+        .mods_channel
+        .create_public_thread(ctx, first, |t| {
+            t.name(shorten(&name, 100)).auto_archive_duration(1440)
+        })
+        .await
+        .context("failed to create the thread")?;
+
+    let contact = ContactThread {
+        thread: thread.id,
+        sheet_id,
+        row: message.row,
+        username: message.username.map(String::from),
+        status: Status::Open,
+        replies: vec![],
+        columns,
+    };
+    contact.save(data)?;
+    contact.update_spreadsheet(data).await?;
+
+    thread
+        .say(ctx, "Use `!contact reply [open|answered|closed] [dm] MESSAGE` here to answer.")
+        .await
+        .context("failed to post the instructions")?;
+
+    Ok(())
 }
 
 async fn inner(ctx: &ErisContext) -> Result<(), Error> {
@@ -120,12 +307,13 @@ async fn inner(ctx: &ErisContext) -> Result<(), Error> {
         .await
         .context("failed to fetch the spreadsheet")?;
 
-    let (sheet_id, unsent) = find_unsent_rows(&spreadsheet)
+    let (sheet_id, columns, unsent) = find_unsent_rows(&spreadsheet)
         .ok_or_else(|| Error::msg("no sheets or required information missing"))?;
 
     for message in unsent {
+        let mut first = None;
         for (i, part) in split_to_parts(message.message, 4096).into_iter().enumerate() {
-            mods_channel
+            let sent = mods_channel
                 .send_message(ctx, |m| {
                     if i == 0 {
                         m.content("New message from the contact form:");
@@ -140,6 +328,14 @@ async fn inner(ctx: &ErisContext) -> Result<(), Error> {
                 })
                 .await
                 .context("failed to forward the message")?;
+            first.get_or_insert(sent.id);
+        }
+
+        // The message has been forwarded, so don't let a missing thread send it again.
+        if let Some(first) = first {
+            if let Err(error) = start_thread(ctx, &data, sheet_id, columns, &message, first).await {
+                error!(?error, "Failed to start a thread for a contact form message");
+            }
         }
 
         sheets
@@ -156,3 +352,37 @@ async fn inner(ctx: &ErisContext) -> Result<(), Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{find_columns, matches_username, Columns};
+    use crate::google::sheets::CellData;
+    use serenity::model::user::User;
+
+    #[test]
+    fn usernames() {
+        let mut user = User::default();
+        user.name = String::from("Ian");
+        user.discriminator = 42;
+        assert!(matches_username(&user, "Ian#0042"));
+        assert!(matches_username(&user, " @ian "));
+        assert!(!matches_username(&user, "Ian#0043"));
+        assert!(!matches_username(&user, "Ian Horner"));
+    }
+
+    #[test]
+    fn columns() {
+        let header = serde_json::from_value::<Vec<CellData>>(serde_json::json!([
+            { "effectiveValue": { "stringValue": "Timestamp" } },
+            { "effectiveValue": { "stringValue": "Message" } },
+            { "effectiveValue": { "stringValue": "Username" } },
+            { "effectiveValue": { "stringValue": "Notes" } },
+            {},
+            { "effectiveValue": { "stringValue": " Status " } },
+            { "effectiveValue": { "stringValue": "Replies" } },
+        ]))
+        .unwrap();
+        assert_eq!(find_columns(&header, 0), Columns { reply: Some(6), status: Some(5) });
+        assert_eq!(find_columns(&header[..4], 2), Columns { reply: None, status: None });
+    }
+}
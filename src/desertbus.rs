@@ -1,33 +1,173 @@
-use anyhow::{Context, Error};
-use chrono::{DateTime, TimeZone};
+use crate::config::Config;
+use crate::extract::Extract;
+use crate::models::State;
+use crate::time::parse_duration;
+use crate::typemap_keys::PgPool;
+use anyhow::{anyhow, Context, Error};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, TimeZone, Timelike, Utc, Weekday};
 use chrono_tz::America::Vancouver as TIMEZONE;
-use chrono_tz::Tz;
 use reqwest::Client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serenity::prelude::TypeMap;
+use std::fmt;
+
+const SETTINGS_STATE_KEY: &str = "eris.desertbus.settings";
+/// The announce window can be at most four weeks on either side of the start.
+const MAX_ANNOUNCE_WINDOW_HOURS: i64 = 4 * 7 * 24;
+
+/// The settings that can be changed with `DesertBusSettings::set`.
+pub const SETTINGS: &[&str] =
+    &["start", "first_hour", "multiplier", "announce_before", "announce_after"];
 
 #[derive(Deserialize)]
 struct Init {
     total: f64,
 }
 
-#[derive(Clone)]
-pub struct DesertBus {
-    client: Client,
+/// Parameters of a Desert Bus run. The defaults come from the `[eris.desertbus]` section and can
+/// be overridden at runtime with `!desertbus set`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DesertBusSettings {
+    pub start: DateTime<FixedOffset>,
+    /// Whether `start` is just `usual_start` for the current year because it hasn't been set.
+    #[serde(default)]
+    pub start_is_guess: bool,
+    /// The price of the first hour.
+    pub first_hour: f64,
+    /// How much more each hour costs than the previous one.
+    pub multiplier: f64,
+    /// How long before the start the run shows up in the topic.
+    pub announce_before_hours: i64,
+    /// How long after the start the run can still show up in the topic.
+    pub announce_after_hours: i64,
 }
 
-impl DesertBus {
-    pub const FIRST_HOUR: f64 = 1.00;
-    pub const MULTIPLIER: f64 = 1.07;
+impl Default for DesertBusSettings {
+    fn default() -> DesertBusSettings {
+        DesertBusSettings {
+            start: usual_start(Utc::now().with_timezone(&TIMEZONE).year()),
+            start_is_guess: true,
+            first_hour: 1.00,
+            multiplier: 1.07,
+            announce_before_hours: 2 * 24,
+            announce_after_hours: 9 * 24,
+        }
+    }
+}
 
-    pub fn new(client: Client) -> DesertBus {
-        DesertBus { client }
+/// When runs usually start: the second Friday of November at 18:00 in Vancouver.
+pub fn usual_start(year: i32) -> DateTime<FixedOffset> {
+    let first = TIMEZONE.ymd(year, 11, 1);
+    let to_friday =
+        (7 + Weekday::Fri.num_days_from_monday() - first.weekday().num_days_from_monday()) % 7;
+    TIMEZONE.ymd(year, 11, 1 + to_friday + 7).and_hms(18, 0, 0).with_timezone(&FixedOffset::east(0))
+}
+
+impl DesertBusSettings {
+    /// The runtime settings if they've been set, otherwise the ones from the config.
+    pub fn load(data: &TypeMap) -> Result<DesertBusSettings, Error> {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+
+        let mut settings =
+            match State::get::<Option<DesertBusSettings>, _>(SETTINGS_STATE_KEY, &conn)
+                .context("failed to load the Desert Bus settings")?
+                .flatten()
+            {
+                Some(settings) => settings,
+                None => data.extract::<Config>()?.desertbus.clone(),
+            };
+        // The bot might have been running since a previous year.
+        if settings.start_is_guess {
+            settings.start = usual_start(Utc::now().with_timezone(&TIMEZONE).year());
+        }
+        Ok(settings)
+    }
+
+    pub fn save(&self, data: &TypeMap) -> Result<(), Error> {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+
+        State::set(SETTINGS_STATE_KEY, self, &conn)
+            .context("failed to save the Desert Bus settings")
+    }
+
+    /// Goes back to the settings from the config.
+    pub fn reset(data: &TypeMap) -> Result<(), Error> {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+
+        State::set(SETTINGS_STATE_KEY, None::<DesertBusSettings>, &conn)
+            .context("failed to reset the Desert Bus settings")
+    }
+
+    /// Sets one of `SETTINGS` from its textual form: an RFC 3339 timestamp for `start`, a number for
+    /// `first_hour` and `multiplier`, and a duration like `2d` or `36h` for the announce window.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        let window = |value: &str| {
+            let hours =
+                parse_duration(value).map(|duration| duration.num_hours()).ok_or_else(|| {
+                    anyhow!("expected a duration like `2d` or `36h`, got {:?}", value)
+                })?;
+            if hours > MAX_ANNOUNCE_WINDOW_HOURS {
+                return Err(anyhow!("{:?} is longer than four weeks", value));
+            }
+            Ok(hours)
+        };
+
+        match key {
+            "start" => {
+                self.start = DateTime::parse_from_rfc3339(value)
+                    .with_context(|| format!("failed to parse {:?} as a timestamp", value))?;
+                self.start_is_guess = false;
+            }
+            "first_hour" | "multiplier" => {
+                let number = value
+                    .parse::<f64>()
+                    .with_context(|| format!("failed to parse {:?} as a number", value))?;
+                if !number.is_finite() || number <= 0.0 || (key == "multiplier" && number <= 1.0) {
+                    return Err(anyhow!("{:?} is out of range for `{}`", value, key));
+                }
+                if key == "first_hour" {
+                    self.first_hour = number;
+                } else {
+                    self.multiplier = number;
+                }
+            }
+            "announce_before" => self.announce_before_hours = window(value)?,
+            "announce_after" => self.announce_after_hours = window(value)?,
+            _ => return Err(anyhow!("expected one of {}, got {:?}", SETTINGS.join(", "), key)),
+        }
+
+        Ok(())
     }
 
-    pub fn start_time() -> DateTime<Tz> {
-        TIMEZONE.ymd(2021, 11, 12).and_hms(18, 0, 0)
+    pub fn start_time(&self) -> DateTime<Utc> {
+        self.start.with_timezone(&Utc)
     }
 
-    pub fn hours_raised(money_raised: f64) -> f64 {
+    /// Whether the run should be talked about at `now`.
+    pub fn in_announce_window(&self, now: DateTime<Utc>) -> bool {
+        let start = self.start_time();
+        // Settings saved before the window was limited might be past what a `Duration` can hold.
+        let before =
+            Duration::hours(self.announce_before_hours.clamp(0, MAX_ANNOUNCE_WINDOW_HOURS));
+        let after = Duration::hours(self.announce_after_hours.clamp(0, MAX_ANNOUNCE_WINDOW_HOURS));
+        let from = start.checked_sub_signed(before);
+        let until = start.checked_add_signed(after);
+        match (from, until) {
+            (Some(from), Some(until)) => from <= now && now <= until,
+            _ => false,
+        }
+    }
+
+    pub fn hours_raised(&self, money_raised: f64) -> f64 {
         // money_raised = FIRST_HOUR + FIRST_HOUR * MULTIPLIER + FIRST_HOUR * MULTIPLIER.pow(2.0) + ... + FIRST_HOUR * MULTIPLIER.pow(hours)
         // money_raised = FIRST_HOUR * (1.0 - MULTIPLIER.pow(hours)) / (1.0 - MULTIPLIER)
         // money_raised / FIRST_HOUR = (MULTIPLIER.pow(hours) - 1.0) / (MULTIPLIER - 1.0)
@@ -35,11 +175,139 @@ impl DesertBus {
         // MULTIPLIER.pow(hours) = money_raised / FIRST_HOUR * (MULTIPLIER - 1.0) + 1.0
         // hours = (money_raised / FIRST_HOUR * (MULTIPLIER - 1.0) + 1.0).log(MULTIPLIER)
 
-        (money_raised / DesertBus::FIRST_HOUR * (DesertBus::MULTIPLIER - 1.0) + 1.0)
-            .log(DesertBus::MULTIPLIER)
+        (money_raised / self.first_hour * (self.multiplier - 1.0) + 1.0)
+            .log(self.multiplier)
             .floor()
     }
 
+    /// How much the first `hours` hours cost in total.
+    pub fn money_needed(&self, hours: i64) -> f64 {
+        self.first_hour * (self.multiplier.powf(hours as f64) - 1.0) / (self.multiplier - 1.0)
+    }
+
+    pub fn progress(&self, money_raised: f64, now: DateTime<Utc>) -> Progress<'_> {
+        Progress {
+            settings: self,
+            money_raised,
+            hours: self.hours_raised(money_raised) as i64,
+            now,
+        }
+    }
+}
+
+/// Where a run stands at `now`, given how much has been raised.
+pub struct Progress<'a> {
+    settings: &'a DesertBusSettings,
+    pub money_raised: f64,
+    /// The hours bought so far.
+    pub hours: i64,
+    pub now: DateTime<Utc>,
+}
+
+impl Progress<'_> {
+    pub fn start(&self) -> DateTime<Utc> {
+        self.settings.start_time()
+    }
+
+    /// When the run ends if no more money comes in.
+    pub fn end(&self) -> DateTime<Utc> {
+        self.start() + Duration::hours(self.hours)
+    }
+
+    pub fn has_started(&self) -> bool {
+        self.start() <= self.now
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.end() < self.now
+    }
+
+    /// How long the run has been going for.
+    pub fn bussed(&self) -> Duration {
+        (self.now - self.start()).max(Duration::zero())
+    }
+
+    /// How much of the hours bought so far are still left.
+    pub fn remaining(&self) -> Duration {
+        self.end() - self.now.max(self.start()).min(self.end())
+    }
+
+    /// How much more needs to be raised to buy another hour.
+    pub fn next_hour(&self) -> f64 {
+        self.settings.money_needed(self.hours + 1) - self.money_raised
+    }
+
+    /// The final total if donations keep coming in at the average rate so far. Only available once
+    /// the run has been going for an hour.
+    pub fn projected_total(&self) -> Option<f64> {
+        let bussed = self.bussed().num_seconds() as f64 / 3600.0;
+        if bussed < 1.0 || self.is_over() {
+            return None;
+        }
+        let rate = self.money_raised / bussed;
+
+        // Every hour raises enough to buy some more, so look for when the run catches up with what
+        // has been bought. The hours only grow logarithmically with the money, so this settles.
+        let mut hours = self.hours as f64;
+        for _ in 0..100 {
+            let total = rate * hours;
+            let next = self.settings.hours_raised(total).max(hours);
+            if next == hours {
+                return Some(total);
+            }
+            hours = next;
+        }
+
+        None
+    }
+}
+
+/// The four six-hour shifts of the bus crew, in the run's local time.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Shift {
+    DawnGuard,
+    AlphaFlight,
+    NightWatch,
+    Zeta,
+}
+
+impl Shift {
+    pub fn at(time: DateTime<Utc>) -> Shift {
+        match time.with_timezone(&TIMEZONE).hour() {
+            6..=11 => Shift::DawnGuard,
+            12..=17 => Shift::AlphaFlight,
+            18..=23 => Shift::NightWatch,
+            _ => Shift::Zeta,
+        }
+    }
+}
+
+impl fmt::Display for Shift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Shift::DawnGuard => "Dawn Guard",
+            Shift::AlphaFlight => "Alpha Flight",
+            Shift::NightWatch => "Night Watch",
+            Shift::Zeta => "Zeta Shift",
+        })
+    }
+}
+
+/// Formats the time spent on the bus like `12:05`.
+pub fn format_bussed(duration: Duration) -> String {
+    format!("{}:{:02}", duration.num_hours(), duration.num_minutes() % 60)
+}
+
+#[derive(Clone)]
+pub struct DesertBus {
+    client: Client,
+}
+
+impl DesertBus {
+    pub fn new(client: Client) -> DesertBus {
+        DesertBus { client }
+    }
+
     pub async fn money_raised(&self) -> Result<f64, Error> {
         Ok(self
             .client
@@ -53,3 +321,128 @@ impl DesertBus {
             .total)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{format_bussed, usual_start, DesertBusSettings, Shift};
+    use chrono::Duration;
+    use chrono::{DateTime, Utc};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn hours_raised() {
+        let settings = DesertBusSettings::default();
+        assert_eq!(settings.hours_raised(0.0), 0.0);
+        assert_eq!(settings.hours_raised(0.99), 0.0);
+        assert_eq!(settings.hours_raised(1.0), 1.0);
+        assert_eq!(settings.hours_raised(2.06), 1.0);
+        assert_eq!(settings.hours_raised(2.08), 2.0);
+        assert_eq!(settings.hours_raised(1_000_000.0), 164.0);
+
+        let settings = DesertBusSettings { first_hour: 10.0, multiplier: 1.5, ..settings };
+        assert_eq!(settings.hours_raised(9.99), 0.0);
+        assert_eq!(settings.hours_raised(26.0), 2.0);
+    }
+
+    #[test]
+    fn announce_window() {
+        let settings =
+            DesertBusSettings { start: usual_start(2021), ..DesertBusSettings::default() };
+        assert!(!settings.in_announce_window(at("2021-11-11T01:59:59Z")));
+        assert!(settings.in_announce_window(at("2021-11-11T02:00:00Z")));
+        assert!(settings.in_announce_window(at("2021-11-22T02:00:00Z")));
+        assert!(!settings.in_announce_window(at("2021-11-22T02:00:01Z")));
+        assert!(!settings.in_announce_window(at("2022-11-12T02:00:00Z")));
+
+        // A run that starts just before New Year's and carries over into the next year.
+        let settings = DesertBusSettings {
+            start: DateTime::parse_from_rfc3339("2022-12-31T18:00:00-08:00").unwrap(),
+            ..settings
+        };
+        assert!(!settings.in_announce_window(at("2021-12-31T12:00:00Z")));
+        assert!(!settings.in_announce_window(at("2022-12-30T01:59:59Z")));
+        assert!(settings.in_announce_window(at("2022-12-30T12:00:00Z")));
+        assert!(settings.in_announce_window(at("2023-01-05T00:00:00Z")));
+        assert!(settings.in_announce_window(at("2023-01-10T02:00:00Z")));
+        assert!(!settings.in_announce_window(at("2023-01-10T02:00:01Z")));
+    }
+
+    #[test]
+    fn progress() {
+        let settings = DesertBusSettings {
+            start: DateTime::parse_from_rfc3339("2022-11-11T14:00:00-08:00").unwrap(),
+            first_hour: 1.0,
+            multiplier: 2.0,
+            ..DesertBusSettings::default()
+        };
+        assert_eq!(settings.money_needed(3), 7.0);
+
+        let progress = settings.progress(10.0, at("2022-11-11T21:00:00Z"));
+        assert!(!progress.has_started());
+        assert_eq!(progress.hours, 3);
+        assert_eq!(progress.bussed(), Duration::zero());
+        assert_eq!(progress.remaining(), Duration::hours(3));
+        assert_eq!(progress.next_hour(), 5.0);
+        assert_eq!(progress.projected_total(), None);
+
+        let progress = settings.progress(30.0, at("2022-11-12T00:30:00Z"));
+        assert!(progress.has_started());
+        assert_eq!(progress.hours, 4);
+        assert_eq!(format_bussed(progress.bussed()), "2:30");
+        assert_eq!(progress.remaining(), Duration::minutes(90));
+        assert_eq!(progress.next_hour(), 1.0);
+        // $12 an hour for 5 hours is $60, which buys exactly those 5 hours.
+        assert_eq!(progress.projected_total(), Some(60.0));
+
+        let progress = settings.progress(30.0, at("2022-11-12T03:00:00Z"));
+        assert!(progress.is_over());
+        assert_eq!(progress.remaining(), Duration::zero());
+        assert_eq!(progress.projected_total(), None);
+    }
+
+    #[test]
+    fn shifts() {
+        assert_eq!(Shift::at(at("2022-11-12T02:00:00Z")), Shift::NightWatch);
+        assert_eq!(Shift::at(at("2022-11-12T08:00:00Z")), Shift::Zeta);
+        assert_eq!(Shift::at(at("2022-11-12T14:00:00Z")), Shift::DawnGuard);
+        assert_eq!(Shift::at(at("2022-11-12T20:00:00Z")), Shift::AlphaFlight);
+        assert_eq!(Shift::AlphaFlight.to_string(), "Alpha Flight");
+    }
+
+    #[test]
+    fn usual_starts() {
+        assert_eq!(usual_start(2021).with_timezone(&Utc), at("2021-11-13T02:00:00Z"));
+        assert_eq!(usual_start(2022).with_timezone(&Utc), at("2022-11-12T02:00:00Z"));
+        // November starts on a Friday.
+        assert_eq!(usual_start(2024).with_timezone(&Utc), at("2024-11-09T02:00:00Z"));
+    }
+
+    #[test]
+    fn set() {
+        let mut settings = DesertBusSettings::default();
+        settings.set("start", "2022-11-11T14:00:00-08:00").unwrap();
+        settings.set("multiplier", "1.075").unwrap();
+        settings.set("announce_before", "1d12h").unwrap();
+        assert_eq!(settings.start_time(), at("2022-11-11T22:00:00Z"));
+        assert!(!settings.start_is_guess);
+        assert_eq!(settings.multiplier, 1.075);
+        assert_eq!(settings.announce_before_hours, 36);
+
+        assert!(settings.set("multiplier", "1").is_err());
+        assert!(settings.set("first_hour", "-1").is_err());
+        assert!(settings.set("announce_after", "soon").is_err());
+        assert!(settings.set("announce_before", "100000000d").is_err());
+        assert!(settings.set("announce_before", "4w").is_err());
+        settings.set("announce_before", "28d").unwrap();
+        assert!(settings.set("start", "next friday").is_err());
+        assert!(settings.set("typo", "1").is_err());
+
+        // Settings saved before the window was limited don't overflow.
+        settings.announce_before_hours = i64::MAX;
+        assert!(settings.in_announce_window(at("2022-10-15T00:00:00Z")));
+        assert!(!settings.in_announce_window(at("2022-10-01T00:00:00Z")));
+    }
+}
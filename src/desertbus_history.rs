@@ -0,0 +1,283 @@
+use crate::context::ErisContext;
+use crate::desertbus::{DesertBus, DesertBusSettings, Progress};
+use crate::extract::Extract;
+use crate::models::State;
+use crate::typemap_keys::PgPool;
+use anyhow::{Context, Error};
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::prelude::TypeMap;
+use std::time::Duration;
+use tracing::error;
+
+/// Runs from before eris kept track of them. Only the final totals of 2007 to 2019 are bundled:
+/// start times, lengths, per-hour totals and runs since then are added with `!desertbus import`.
+const BUNDLED: &str = include_str!("../data/desertbus.json");
+/// Runs eris has seen end.
+const HISTORY_STATE_KEY: &str = "eris.desertbus.history";
+/// The run that's currently going on, if any.
+const CURRENT_STATE_KEY: &str = "eris.desertbus.current_run";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Run {
+    pub year: i32,
+    #[serde(default)]
+    pub start: Option<DateTime<FixedOffset>>,
+    pub total: f64,
+    #[serde(default)]
+    pub hours: Option<i64>,
+    /// The total at the start of each hour of the run, if it's known.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hourly: Vec<Option<f64>>,
+}
+
+impl Run {
+    /// Desert Bus for Hope 1 was in 2007.
+    pub fn number(&self) -> i32 {
+        self.year - 2006
+    }
+
+    pub fn at_hour(&self, hour: usize) -> Option<f64> {
+        self.hourly.get(hour).copied().flatten()
+    }
+}
+
+/// The bundled runs with the recorded ones on top, ordered by year.
+fn merge(bundled: Vec<Run>, recorded: Vec<Run>) -> Vec<Run> {
+    let mut runs = bundled;
+    for run in recorded {
+        match runs.iter_mut().find(|bundled| bundled.year == run.year) {
+            Some(bundled) => *bundled = run,
+            None => runs.push(run),
+        }
+    }
+    runs.sort_by_key(|run| run.year);
+    runs
+}
+
+fn load_recorded(data: &TypeMap) -> Result<Vec<Run>, Error> {
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a database connection from the pool")?;
+    Ok(State::get::<Vec<Run>, _>(HISTORY_STATE_KEY, &conn)
+        .context("failed to load the recorded Desert Bus history")?
+        .unwrap_or_default())
+}
+
+/// Adds `run` to the history, replacing whatever was known about that year.
+pub fn save_run(data: &TypeMap, run: Run) -> Result<(), Error> {
+    let mut recorded = load_recorded(data)?;
+    recorded.retain(|recorded| recorded.year != run.year);
+    recorded.push(run);
+
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a database connection from the pool")?;
+    State::set(HISTORY_STATE_KEY, &recorded, &conn)
+        .context("failed to save the recorded Desert Bus history")
+}
+
+/// Parses the per-hour totals of a run from `HOUR,TOTAL` lines. Hours that aren't listed are
+/// left unknown, and lines that don't start with a number, like a header, are skipped.
+pub fn parse_hourly(csv: &str) -> Result<Vec<Option<f64>>, Error> {
+    let mut hourly = vec![];
+    for line in csv.lines() {
+        let line = line.trim();
+        if !line.starts_with(|c: char| c.is_ascii_digit()) {
+            continue;
+        }
+        let (hour, total) =
+            line.split_once(',').with_context(|| format!("expected HOUR,TOTAL: {:?}", line))?;
+        let hour = hour.trim().parse::<usize>().with_context(|| format!("bad hour: {:?}", line))?;
+        let total = total
+            .trim()
+            .trim_start_matches('$')
+            .replace(',', "")
+            .parse::<f64>()
+            .with_context(|| format!("bad total: {:?}", line))?;
+        if hourly.len() <= hour {
+            hourly.resize(hour + 1, None);
+        }
+        hourly[hour] = Some(total);
+    }
+    Ok(hourly)
+}
+
+/// All the past runs.
+pub fn history(data: &TypeMap) -> Result<Vec<Run>, Error> {
+    let bundled =
+        serde_json::from_str(BUNDLED).context("failed to parse the bundled Desert Bus history")?;
+
+    Ok(merge(bundled, load_recorded(data)?))
+}
+
+/// Adds what's happening now to the run being recorded. Returns the run that's still going on, if
+/// it is, and the run that has just ended, if it has.
+fn record(
+    current: Option<Run>,
+    settings: &DesertBusSettings,
+    progress: &Progress,
+) -> (Option<Run>, Option<Run>) {
+    let mut run = match current {
+        Some(run) if run.start == Some(settings.start) => run,
+        // Only record runs that were seen while they were going on.
+        _ if progress.is_over() => return (None, None),
+        _ => Run {
+            year: settings.start.year(),
+            start: Some(settings.start),
+            total: 0.0,
+            hours: None,
+            hourly: vec![],
+        },
+    };
+
+    let bussed = progress.bussed();
+    let hour = bussed.num_hours() as usize;
+    // Hours that were missed, say because the bot was down, are left unknown rather than filled in
+    // with a later total. So is the current one, unless it's just started.
+    while run.hourly.len() < hour {
+        run.hourly.push(None);
+    }
+    if run.hourly.len() == hour {
+        let late = bussed - ChronoDuration::hours(hour as i64) > ChronoDuration::minutes(5);
+        run.hourly.push(if late { None } else { Some(progress.money_raised) });
+    }
+    run.total = progress.money_raised;
+    run.hours = Some(progress.hours);
+
+    if progress.is_over() {
+        (None, Some(run))
+    } else {
+        (Some(run), None)
+    }
+}
+
+async fn inner(ctx: &ErisContext) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+
+    let now = Utc::now();
+    let settings = DesertBusSettings::load(&data)?;
+    if !settings.in_announce_window(now) || now < settings.start_time() {
+        return Ok(());
+    }
+
+    let money_raised = data.extract::<DesertBus>()?.money_raised().await?;
+    let progress = settings.progress(money_raised, now);
+
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a DB connection from the connection pool")?;
+
+    let current = State::get::<Option<Run>, _>(CURRENT_STATE_KEY, &conn)
+        .context("failed to get the current Desert Bus run")?
+        .flatten();
+    let had_current = current.is_some();
+    let (current, finished) = record(current, &settings, &progress);
+
+    if let Some(finished) = finished {
+        save_run(&data, finished)?;
+    }
+
+    if current.is_some() || had_current {
+        State::set(CURRENT_STATE_KEY, &current, &conn)
+            .context("failed to save the current Desert Bus run")?;
+    }
+
+    Ok(())
+}
+
+pub async fn record_runs(ctx: ErisContext) {
+    let mut timer = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        timer.tick().await;
+
+        if let Err(error) = inner(&ctx).await {
+            error!(?error, "Failed to record the Desert Bus run");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, parse_hourly, record, Run, BUNDLED};
+    use crate::desertbus::DesertBusSettings;
+    use chrono::{DateTime, Utc};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn bundled() {
+        let runs = serde_json::from_str::<Vec<Run>>(BUNDLED).unwrap();
+        assert_eq!(runs[0].year, 2007);
+        assert_eq!(runs[0].number(), 1);
+        assert!(runs.windows(2).all(|runs| runs[0].year < runs[1].year));
+    }
+
+    #[test]
+    fn merging() {
+        let run = |year, total| Run { year, start: None, total, hours: None, hourly: vec![] };
+        let runs =
+            merge(vec![run(2007, 1.0), run(2008, 2.0)], vec![run(2009, 4.0), run(2008, 3.0)]);
+        assert_eq!(runs, vec![run(2007, 1.0), run(2008, 3.0), run(2009, 4.0)]);
+    }
+
+    #[test]
+    fn recording() {
+        let settings = DesertBusSettings {
+            start: DateTime::parse_from_rfc3339("2022-11-11T14:00:00-08:00").unwrap(),
+            first_hour: 1.0,
+            multiplier: 2.0,
+            ..DesertBusSettings::default()
+        };
+
+        let (current, finished) =
+            record(None, &settings, &settings.progress(10.0, at("2022-11-11T22:01:00Z")));
+        assert_eq!(finished, None);
+        let current = current.unwrap();
+        assert_eq!(current.year, 2022);
+        assert_eq!(current.hourly, vec![Some(10.0)]);
+
+        // Missed hours are left unknown.
+        let (current, finished) =
+            record(Some(current), &settings, &settings.progress(20.0, at("2022-11-12T00:01:00Z")));
+        assert_eq!(finished, None);
+        let current = current.unwrap();
+        assert_eq!(current.hourly, vec![Some(10.0), None, Some(20.0)]);
+        assert_eq!(current.at_hour(1), None);
+        assert_eq!(current.at_hour(2), Some(20.0));
+
+        // So is an hour that was first seen well after it started.
+        let (current, _) =
+            record(Some(current), &settings, &settings.progress(25.0, at("2022-11-12T01:20:00Z")));
+        let current = current.unwrap();
+        assert_eq!(current.hourly, vec![Some(10.0), None, Some(20.0), None]);
+
+        let (current, finished) =
+            record(Some(current), &settings, &settings.progress(30.0, at("2022-11-12T02:10:00Z")));
+        assert_eq!(current, None);
+        let finished = finished.unwrap();
+        assert_eq!(finished.total, 30.0);
+        assert_eq!(finished.hours, Some(4));
+
+        // A run that ended while nobody was watching isn't recorded.
+        assert_eq!(
+            record(None, &settings, &settings.progress(30.0, at("2022-11-12T02:20:00Z"))),
+            (None, None)
+        );
+    }
+
+    #[test]
+    fn hourly_csv() {
+        assert_eq!(
+            parse_hourly("hour,total\n0,1.00\n1, $2,500.50\n3,10\n").unwrap(),
+            vec![Some(1.0), Some(2500.5), None, Some(10.0)]
+        );
+        assert!(parse_hourly("0;1.00").is_err());
+    }
+}
@@ -0,0 +1,56 @@
+//! Requests to the parts of the Discord API that Serenity doesn't know about yet, like guild
+//! stickers and scheduled events.
+
+use anyhow::{Context, Error};
+use reqwest::{Client, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+const API_BASE: &str = "https://discord.com/api/v9";
+
+#[derive(Clone)]
+pub struct DiscordApi {
+    client: Client,
+    token: String,
+}
+
+impl DiscordApi {
+    /// `token` is the bot token as sent in the `Authorization` header, "Bot " prefix included.
+    pub fn new(client: Client, token: String) -> DiscordApi {
+        DiscordApi { client, token }
+    }
+
+    /// `path` is relative to the API base, like `/guilds/1/stickers`.
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        self.send(self.client.get(format!("{}{}", API_BASE, path))).await
+    }
+
+    pub async fn post<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, Error> {
+        self.send(self.client.post(format!("{}{}", API_BASE, path)).json(body)).await
+    }
+
+    pub async fn patch<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, Error> {
+        self.send(self.client.patch(format!("{}{}", API_BASE, path)).json(body)).await
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, Error> {
+        request
+            .header(reqwest::header::AUTHORIZATION, &self.token)
+            .send()
+            .await
+            .context("failed to send the request")?
+            .error_for_status()
+            .context("request failed")?
+            .json()
+            .await
+            .context("failed to parse the response")
+    }
+}
@@ -1,12 +1,17 @@
-use crate::config::Config;
+use crate::commands::voice::{create_temp_channel, lobby_channel_name};
+use crate::config::{AfkPolicy, Config};
+use crate::emoji::EmojiCache;
 use crate::extract::Extract;
 use crate::influxdb::{InfluxDB, Measurement, New, Timestamp};
+use crate::models::TempChannel;
+use crate::typemap_keys::PgPool;
 use anyhow::{bail, Context as _, Error};
+use chrono::Utc;
 use joinery::Joinable;
 use serenity::async_trait;
-use serenity::http::client::Http;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
+use serenity::utils::MessageBuilder;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::future::Future;
@@ -78,13 +83,138 @@ impl DiscordEvents {
         measurement
     }
 
-    async fn kick_from_voice(
+    /// Applies `Config::afk_policy` to someone in the AFK channel and lets the mods know.
+    async fn enforce_afk_policy(
         &self,
-        http: &Http,
-        guild: GuildId,
+        ctx: &Context,
+        data: &TypeMap,
+        guild: &Guild,
         user: UserId,
     ) -> Result<(), Error> {
-        guild.disconnect_member(http, user).await?;
+        let config = data.extract::<Config>()?;
+        if config.afk_policy == AfkPolicy::Ignore {
+            return Ok(());
+        }
+
+        let member = guild.member(ctx, user).await.context("failed to get the member")?;
+        if member.roles.iter().any(|role| config.afk_exempt_roles.contains(role)) {
+            return Ok(());
+        }
+
+        let mut log = MessageBuilder::new();
+        let action = match config.afk_policy {
+            AfkPolicy::Kick => {
+                guild.id.disconnect_member(ctx, user).await?;
+                log.push("Disconnected ").mention(&user);
+                "kick"
+            }
+            AfkPolicy::Move(channel) => {
+                guild.id.move_member(ctx, user, channel).await?;
+                log.push("Moved ").mention(&user).push(" to ").channel(channel);
+                "move"
+            }
+            AfkPolicy::Ignore => return Ok(()),
+        };
+        log.push(" (").push_safe(member.user.tag()).push(") for joining the AFK channel.");
+
+        config
+            .mods_channel
+            .send_message(ctx, |m| m.content(log.build()).allowed_mentions(|am| am.empty_parse()))
+            .await
+            .context("failed to log the AFK channel action")?;
+
+        if let Some(influxdb) = data.get::<InfluxDB>() {
+            let measurement = Measurement::new("afk_channel", Timestamp::Now)
+                .add_tag("action", action)
+                .add_field("count", 1);
+            influxdb
+                .write(&[measurement])
+                .await
+                .context("failed to write the AFK channel action to InfluxDB")?;
+        }
+
+        Ok(())
+    }
+
+    /// Records that temporary channels were occupied until now, so the reaper knows how long
+    /// they've been empty.
+    fn touch_temp_channels(
+        data: &TypeMap,
+        guild: &Guild,
+        channels: impl Iterator<Item = ChannelId>,
+    ) -> Result<(), Error> {
+        let config = data.extract::<Config>()?;
+        let mut channels = channels
+            .filter(|channel| match guild.channels.get(channel) {
+                Some(channel) => channel.name.starts_with(&config.temp_channel_prefix),
+                None => false,
+            })
+            .peekable();
+        if channels.peek().is_none() {
+            return Ok(());
+        }
+
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a database connection from the pool")?;
+        for channel in channels {
+            TempChannel::touch(channel.0 as i64, Utc::now(), &conn)
+                .context("failed to update when a temporary channel was last occupied")?;
+        }
+        Ok(())
+    }
+
+    /// Moves someone who joined the lobby into a new temporary channel, or into one of theirs if
+    /// they can't have any more.
+    async fn join_lobby(
+        &self,
+        ctx: &Context,
+        data: &TypeMap,
+        guild: &Guild,
+        user: UserId,
+    ) -> Result<(), Error> {
+        let config = data.extract::<Config>()?;
+
+        let owned = {
+            let conn = data
+                .extract::<PgPool>()?
+                .get()
+                .context("failed to get a database connection from the pool")?;
+            TempChannel::owned_by(user.0 as i64, &conn)
+                .context("failed to get the user's temporary channels")?
+        };
+        if owned.len() >= config.temp_channel_limit {
+            if let Some(channel) = owned
+                .iter()
+                .map(|channel| ChannelId(channel.id as u64))
+                .find(|channel| guild.channels.contains_key(channel))
+            {
+                guild.move_member(ctx, user, channel).await?;
+                return Ok(());
+            }
+        }
+
+        let member = guild.member(ctx, user).await.context("failed to get the member")?;
+        let game = guild.presences.get(&user).and_then(|presence| {
+            presence
+                .activities
+                .iter()
+                .find(|activity| activity.kind == ActivityType::Playing)
+                .map(|activity| activity.name.as_str())
+        });
+        let name = lobby_channel_name(
+            &config.voice_lobby_template,
+            &config.voice_lobby_fallback,
+            member.display_name().as_str(),
+            game,
+        )?;
+
+        let channel = create_temp_channel(ctx, data, user, &name)
+            .await
+            .context("failed to create a temporary channel")?;
+        guild.move_member(ctx, user, channel.id).await?;
+
         Ok(())
     }
 
@@ -234,11 +364,17 @@ impl EventHandler for DiscordEvents {
     }
 
     async fn guild_create(&self, ctx: Context, guild: Guild, _is_new: bool) {
+        // The emojis and stickers might have changed while we were disconnected.
+        if let Some(emoji_cache) = ctx.data.read().await.get::<EmojiCache>() {
+            emoji_cache.invalidate(guild.id).await;
+        }
+
         if let Some(afk_channel) = guild.afk_channel_id {
+            let data = ctx.data.read().await;
             for (&user, voice_state) in &guild.voice_states {
                 if voice_state.channel_id == Some(afk_channel) {
-                    if let Err(error) = self.kick_from_voice(&ctx.http, guild.id, user).await {
-                        error!(?error, "failed to kick user from the AFK channel");
+                    if let Err(error) = self.enforce_afk_policy(&ctx, &data, &guild, user).await {
+                        error!(?error, "failed to enforce the AFK channel policy");
                     }
                 }
             }
@@ -286,6 +422,29 @@ impl EventHandler for DiscordEvents {
         .await
     }
 
+    async fn guild_emojis_update(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        _current_state: HashMap<EmojiId, Emoji>,
+    ) {
+        if let Some(emoji_cache) = ctx.data.read().await.get::<EmojiCache>() {
+            emoji_cache.invalidate(guild_id).await;
+        }
+    }
+
+    async fn unknown(&self, ctx: Context, name: String, raw: serde_json::Value) {
+        // Serenity doesn't know about stickers yet.
+        if name == "GUILD_STICKERS_UPDATE" {
+            let guild_id = raw.get("guild_id").and_then(|id| id.as_str()?.parse().ok());
+            if let (Some(guild_id), Some(emoji_cache)) =
+                (guild_id, ctx.data.read().await.get::<EmojiCache>())
+            {
+                emoji_cache.invalidate(GuildId(guild_id)).await;
+            }
+        }
+    }
+
     async fn voice_state_update(
         &self,
         ctx: Context,
@@ -298,12 +457,29 @@ impl EventHandler for DiscordEvents {
 
             if let Some(guild) = guild {
                 if let Some(guild) = guild.to_guild_cached(&ctx).await {
+                    let lobby = data.extract::<Config>()?.voice_lobby;
+                    let old_channel = old.as_ref().and_then(|state| state.channel_id);
+
+                    if old_channel != new.channel_id {
+                        let channels = old_channel.into_iter().chain(new.channel_id);
+                        if let Err(error) = Self::touch_temp_channels(&data, &guild, channels) {
+                            error!(?error, "failed to update the temporary channels");
+                        }
+                    }
+
+                    if lobby.is_some() && new.channel_id == lobby && old_channel != lobby {
+                        if let Err(error) = self.join_lobby(&ctx, &data, &guild, new.user_id).await
+                        {
+                            error!(?error, "failed to move a user out of the voice lobby");
+                        }
+                    }
+
                     if let Some(afk_channel) = guild.afk_channel_id {
                         if new.channel_id == Some(afk_channel) {
                             if let Err(error) =
-                                self.kick_from_voice(&ctx.http, guild.id, new.user_id).await
+                                self.enforce_afk_policy(&ctx, &data, &guild, new.user_id).await
                             {
-                                error!(?error, "failed to kick user from the AFK channel");
+                                error!(?error, "failed to enforce the AFK channel policy");
                             }
                         }
                     }
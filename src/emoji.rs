@@ -0,0 +1,359 @@
+//! Guild emojis and stickers in static responses.
+//!
+//! Serenity doesn't know about guild stickers yet, so they're fetched from the API directly.
+
+use crate::discord_api::DiscordApi;
+use anyhow::{Context, Error};
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use serenity::model::prelude::*;
+use serenity::prelude::RwLock;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::error;
+
+/// Discord allows at most this many stickers on a message.
+const MAX_STICKERS: usize = 3;
+
+/// https://discord.com/developers/docs/resources/sticker#sticker-object
+#[derive(Debug, Clone, Deserialize)]
+pub struct GuildSticker {
+    pub id: StickerId,
+    pub name: String,
+    #[serde(default = "available_by_default")]
+    pub available: bool,
+}
+
+fn available_by_default() -> bool {
+    true
+}
+
+#[derive(Debug)]
+enum Substitution {
+    Emoji(String),
+    Sticker(StickerId),
+}
+
+/// Replaces the names of guild emojis in text with the emojis themselves, and picks out the
+/// names of guild stickers.
+pub struct EmojiMatcher {
+    // `None` when the guild has no usable emojis or stickers.
+    regex: Option<Regex>,
+    substitutions: HashMap<String, Substitution>,
+}
+
+impl EmojiMatcher {
+    pub fn new<'a, I: IntoIterator<Item = &'a Emoji>>(
+        emojis: I,
+        stickers: &[GuildSticker],
+    ) -> Result<EmojiMatcher, Error> {
+        let mut substitutions = HashMap::new();
+        for emoji in emojis {
+            if !emoji.available {
+                continue;
+            }
+
+            let mention = if emoji.animated {
+                format!("<a:_:{}>", emoji.id.0)
+            } else {
+                emoji.mention().to_string()
+            };
+            substitutions.entry(emoji.name.clone()).or_insert(Substitution::Emoji(mention));
+        }
+        // Emojis win if a sticker has the same name, they can go anywhere in the text.
+        for sticker in stickers {
+            if sticker.available {
+                substitutions
+                    .entry(sticker.name.clone())
+                    .or_insert(Substitution::Sticker(sticker.id));
+            }
+        }
+
+        let regex = if substitutions.is_empty() {
+            None
+        } else {
+            // The alternation is leftmost-first, so try longer names first in case one name is
+            // a prefix of another.
+            let mut names =
+                substitutions.keys().map(|name| regex::escape(name)).collect::<Vec<_>>();
+            names.sort_unstable_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+            let regex = Regex::new(&format!(r"\b(?:{})\b", names.join("|")))
+                .context("failed to compile the emoji regex")?;
+            Some(regex)
+        };
+
+        Ok(EmojiMatcher { regex, substitutions })
+    }
+
+    /// Replaces the emoji names in `text`. Sticker names are left alone.
+    pub fn replace<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        match self.regex {
+            Some(ref regex) => regex.replace_all(text, |captures: &Captures| {
+                match self.substitutions.get(&captures[0]) {
+                    Some(Substitution::Emoji(mention)) => mention.clone(),
+                    _ => captures[0].to_string(),
+                }
+            }),
+            None => Cow::Borrowed(text),
+        }
+    }
+
+    /// Replaces the emoji names in `text` and takes out the sticker names, up to as many as fit
+    /// on a message. Returns the text and the stickers to send with it.
+    pub fn replace_with_stickers(&self, text: &str) -> (String, Vec<StickerId>) {
+        let regex = match self.regex {
+            Some(ref regex) => regex,
+            None => return (String::from(text), vec![]),
+        };
+
+        let mut stickers = vec![];
+        let mut replaced = String::with_capacity(text.len());
+        let mut last = 0;
+        for m in regex.find_iter(text) {
+            let take = match self.substitutions.get(m.as_str()) {
+                Some(Substitution::Emoji(mention)) => {
+                    replaced.push_str(&text[last..m.start()]);
+                    replaced.push_str(mention);
+                    last = m.end();
+                    continue;
+                }
+                Some(Substitution::Sticker(id)) if stickers.contains(id) => true,
+                Some(Substitution::Sticker(id)) if stickers.len() < MAX_STICKERS => {
+                    stickers.push(*id);
+                    true
+                }
+                _ => false,
+            };
+            if !take {
+                continue;
+            }
+
+            // Take out the sticker name along with the spaces around it, keeping one between
+            // words and dropping lines that end up empty.
+            replaced.push_str(text[last..m.start()].trim_end_matches(is_blank));
+            let rest = text[m.end()..].trim_start_matches(is_blank);
+            last = text.len() - rest.len();
+            let line_start = replaced.is_empty() || replaced.ends_with('\n');
+            if line_start && rest.starts_with('\n') {
+                last += 1;
+            } else if !line_start && !rest.is_empty() && !rest.starts_with('\n') {
+                replaced.push(' ');
+            }
+        }
+        replaced.push_str(&text[last..]);
+
+        (replaced, stickers)
+    }
+}
+
+fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+/// Per-guild cache of `EmojiMatcher`s. Entries are dropped when the guild's emojis or stickers
+/// change.
+pub struct EmojiCache {
+    api: DiscordApi,
+    matchers: RwLock<HashMap<GuildId, Arc<EmojiMatcher>>>,
+}
+
+impl EmojiCache {
+    pub fn new(api: DiscordApi) -> EmojiCache {
+        EmojiCache { api, matchers: RwLock::new(HashMap::new()) }
+    }
+
+    pub async fn get(&self, guild: &Guild) -> Result<Arc<EmojiMatcher>, Error> {
+        if let Some(matcher) = self.matchers.read().await.get(&guild.id) {
+            return Ok(matcher.clone());
+        }
+
+        match self.api.get::<Vec<GuildSticker>>(&format!("/guilds/{}/stickers", guild.id.0)).await {
+            Ok(stickers) => {
+                let matcher = Arc::new(EmojiMatcher::new(guild.emojis.values(), &stickers)?);
+                self.matchers.write().await.insert(guild.id, matcher.clone());
+                Ok(matcher)
+            }
+            Err(error) => {
+                // Still replace the emojis, and try the stickers again next time.
+                error!(?error, guild.id = guild.id.0, "Failed to get the guild stickers");
+                Ok(Arc::new(EmojiMatcher::new(guild.emojis.values(), &[])?))
+            }
+        }
+    }
+
+    pub async fn invalidate(&self, guild_id: GuildId) {
+        self.matchers.write().await.remove(&guild_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmojiMatcher, GuildSticker};
+    use serenity::model::guild::Emoji;
+    use serenity::model::id::StickerId;
+
+    #[test]
+    fn replace() {
+        let emoji = serde_json::from_str::<Vec<Emoji>>(
+            r#"
+            [
+                {
+                    "animated": false,
+                    "id": "1",
+                    "name": "lrrDOTS",
+                    "managed": true,
+                    "require_colons": true,
+                    "roles": []
+                },
+                {
+                    "animated": false,
+                    "id": "2",
+                    "name": "lrrCIRCLE",
+                    "managed": true,
+                    "require_colons": true,
+                    "roles": []
+                },
+                {
+                    "animated": false,
+                    "id": "3",
+                    "name": "lrrARROW",
+                    "managed": true,
+                    "require_colons": true,
+                    "roles": []
+                },
+                {
+                    "animated": false,
+                    "id": "4",
+                    "name": "lrrARROWS",
+                    "managed": true,
+                    "require_colons": true,
+                    "roles": []
+                },
+                {
+                    "animated": true,
+                    "id": "5",
+                    "name": "lrrSPIN",
+                    "managed": true,
+                    "require_colons": true,
+                    "roles": []
+                },
+                {
+                    "animated": false,
+                    "available": false,
+                    "id": "6",
+                    "name": "lrrGONE",
+                    "managed": true,
+                    "require_colons": true,
+                    "roles": []
+                }
+            ]
+        "#,
+        )
+        .unwrap();
+
+        let matcher = EmojiMatcher::new(emoji.iter(), &[]).unwrap();
+
+        assert_eq!(
+            matcher.replace(
+                "lrrDOTS lrrCIRCLE lrrARROW Visit LoadingReadyRun: http://loadingreadyrun.com/"
+            ),
+            "<:_:1> <:_:2> <:_:3> Visit LoadingReadyRun: http://loadingreadyrun.com/"
+        );
+        assert_eq!(matcher.replace("lrrARROWS lrrSPIN lrrGONE"), "<:_:4> <a:_:5> lrrGONE");
+        assert_eq!(matcher.replace("notlrrDOTS lrrDOTSnt"), "notlrrDOTS lrrDOTSnt");
+    }
+
+    #[test]
+    fn no_emojis() {
+        let matcher = EmojiMatcher::new(&[], &[]).unwrap();
+        assert_eq!(matcher.replace("lrrDOTS"), "lrrDOTS");
+    }
+
+    fn emoji(id: u64, name: &str) -> Emoji {
+        serde_json::from_value(serde_json::json!({
+            "animated": false,
+            "id": id.to_string(),
+            "name": name,
+            "managed": true,
+            "require_colons": true,
+            "roles": [],
+        }))
+        .unwrap()
+    }
+
+    fn sticker(id: u64, name: &str) -> GuildSticker {
+        GuildSticker { id: StickerId(id), name: String::from(name), available: true }
+    }
+
+    #[test]
+    fn stickers() {
+        let emojis = vec![emoji(1, "lrrDOTS"), emoji(2, "lrrHEART")];
+        let stickers =
+            vec![sticker(10, "lrrHEART"), sticker(11, "benginoBus"), sticker(12, "lrrWAVE")];
+        let matcher = EmojiMatcher::new(&emojis, &stickers).unwrap();
+
+        assert_eq!(matcher.replace("lrrDOTS benginoBus"), "<:_:1> benginoBus");
+        assert_eq!(
+            matcher.replace_with_stickers("lrrDOTS benginoBus lrrHEART"),
+            (String::from("<:_:1> <:_:2>"), vec![StickerId(11)])
+        );
+        assert_eq!(
+            matcher.replace_with_stickers("benginoBus lrrWAVE benginoBus"),
+            (String::new(), vec![StickerId(11), StickerId(12)])
+        );
+        assert_eq!(matcher.replace_with_stickers("Hi there"), (String::from("Hi there"), vec![]));
+        assert_eq!(
+            matcher
+                .replace_with_stickers("Welcome  aboard!\nbenginoBus\nNext stop:\tlrrWAVE Vegas"),
+            (
+                String::from("Welcome  aboard!\nNext stop: Vegas"),
+                vec![StickerId(11), StickerId(12)]
+            )
+        );
+    }
+
+    /// Compares the cached matcher to compiling a regex per emoji for every response, like
+    /// static responses used to. Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn benchmark() {
+        use regex::Regex;
+        use serenity::prelude::Mentionable;
+        use std::time::Instant;
+
+        const RESPONSES: u32 = 100;
+
+        let emojis = (0..300).map(|i| emoji(i + 1, &format!("lrrEMOJI{}", i))).collect::<Vec<_>>();
+        let text =
+            "lrrEMOJI1 lrrEMOJI150 Visit LoadingReadyRun: http://loadingreadyrun.com/ lrrEMOJI299";
+
+        let old = Instant::now();
+        for _ in 0..RESPONSES {
+            let mut msg = String::from(text);
+            for emoji in &emojis {
+                let regex = Regex::new(&format!(r"\b{}\b", regex::escape(&emoji.name))).unwrap();
+                msg = regex.replace_all(&msg, emoji.mention().to_string().as_str()).into_owned();
+            }
+            assert!(msg.starts_with("<:_:2>"));
+        }
+        let old = old.elapsed();
+
+        let new = Instant::now();
+        let matcher = EmojiMatcher::new(&emojis, &[]).unwrap();
+        for _ in 0..RESPONSES {
+            assert!(matcher.replace(text).starts_with("<:_:2>"));
+        }
+        let new = new.elapsed();
+
+        println!(
+            "{} responses with {} emojis: per-emoji regexes {:?}, cached matcher {:?} ({:.0}x)",
+            RESPONSES,
+            emojis.len(),
+            old,
+            new,
+            old.as_secs_f64() / new.as_secs_f64()
+        );
+        assert!(new < old);
+    }
+}
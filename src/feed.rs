@@ -0,0 +1,223 @@
+use anyhow::{bail, Context, Error};
+use chrono::{DateTime, FixedOffset};
+use reqwest::Client;
+use roxmltree::{Document, Node};
+
+const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+
+/// An RSS or an Atom feed.
+#[derive(Debug, PartialEq)]
+pub struct Feed {
+    pub title: Option<String>,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub link: Option<String>,
+    pub published: Option<DateTime<FixedOffset>>,
+}
+
+fn child<'a, 'input>(
+    node: Node<'a, 'input>,
+    ns: Option<&str>,
+    name: &str,
+) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.tag_name().namespace() == ns && child.tag_name().name() == name)
+}
+
+fn child_text(node: Node, ns: Option<&str>, name: &str) -> Option<String> {
+    child(node, ns, name)
+        .and_then(|child| child.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(String::from)
+}
+
+impl Feed {
+    pub fn parse(xml: &str) -> Result<Feed, Error> {
+        let document = Document::parse(xml).context("failed to parse the feed")?;
+        let root = document.root_element();
+
+        match (root.tag_name().namespace(), root.tag_name().name()) {
+            (None, "rss") => {
+                let channel = child(root, None, "channel").context("RSS feed without a channel")?;
+                Ok(Feed {
+                    title: child_text(channel, None, "title"),
+                    entries: channel
+                        .children()
+                        .filter(|node| node.tag_name().namespace().is_none())
+                        .filter(|node| node.tag_name().name() == "item")
+                        .map(Entry::from_rss_item)
+                        .collect(),
+                })
+            }
+            (Some(ATOM_NS), "feed") => Ok(Feed {
+                title: child_text(root, Some(ATOM_NS), "title"),
+                entries: root
+                    .children()
+                    .filter(|node| node.tag_name().namespace() == Some(ATOM_NS))
+                    .filter(|node| node.tag_name().name() == "entry")
+                    .map(Entry::from_atom_entry)
+                    .collect(),
+            }),
+            (_, name) => bail!("unknown feed format with root element {:?}", name),
+        }
+    }
+}
+
+impl Entry {
+    fn from_rss_item(item: Node) -> Entry {
+        Entry {
+            id: child_text(item, None, "guid"),
+            title: child_text(item, None, "title"),
+            link: child_text(item, None, "link"),
+            published: child_text(item, None, "pubDate")
+                .and_then(|date| DateTime::parse_from_rfc2822(&date).ok()),
+        }
+    }
+
+    fn from_atom_entry(entry: Node) -> Entry {
+        let link = entry
+            .children()
+            .filter(|node| node.tag_name().namespace() == Some(ATOM_NS))
+            .filter(|node| node.tag_name().name() == "link")
+            .find(|node| node.attribute("rel").map(|rel| rel == "alternate").unwrap_or(true))
+            .and_then(|node| node.attribute("href"))
+            .map(String::from);
+
+        Entry {
+            id: child_text(entry, Some(ATOM_NS), "id"),
+            title: child_text(entry, Some(ATOM_NS), "title"),
+            link,
+            published: child_text(entry, Some(ATOM_NS), "published")
+                .or_else(|| child_text(entry, Some(ATOM_NS), "updated"))
+                .and_then(|date| DateTime::parse_from_rfc3339(&date).ok()),
+        }
+    }
+}
+
+/// Fetches RSS and Atom feeds.
+#[derive(Clone)]
+pub struct Feeds {
+    client: Client,
+}
+
+impl Feeds {
+    pub fn new(client: Client) -> Feeds {
+        Feeds { client }
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Feed, Error> {
+        let body = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .context("failed to send the request")?
+            .error_for_status()
+            .context("request failed")?
+            .text()
+            .await
+            .context("failed to read the response")?;
+
+        Feed::parse(&body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, Feed, Feeds};
+    use chrono::DateTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const YOUTUBE: &str = include_str!("../fixtures/feeds/youtube.xml");
+    const MASTODON: &str = include_str!("../fixtures/feeds/mastodon.rss");
+
+    /// Serve `body` to a single HTTP request.
+    async fn serve_once(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("failed to bind");
+        let addr = listener.local_addr().expect("failed to get the local address");
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("failed to accept");
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).await.expect("failed to read the request");
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.expect("failed to write the response");
+        });
+
+        format!("http://{}/feed", addr)
+    }
+
+    #[test]
+    fn parse_atom() {
+        let feed = Feed::parse(YOUTUBE).unwrap();
+        assert_eq!(feed.title.as_deref(), Some("LoadingReadyRun"));
+        assert_eq!(
+            feed.entries,
+            vec![
+                Entry {
+                    id: Some("yt:video:bbbbbbbbbbb".into()),
+                    title: Some("CheckPoint: The Newest Video".into()),
+                    link: Some("https://www.youtube.com/watch?v=bbbbbbbbbbb".into()),
+                    published: Some(
+                        DateTime::parse_from_rfc3339("2021-12-20T20:00:00+00:00").unwrap()
+                    ),
+                },
+                Entry {
+                    id: Some("yt:video:aaaaaaaaaaa".into()),
+                    title: Some("Road Quest: An Older Video".into()),
+                    link: Some("https://www.youtube.com/watch?v=aaaaaaaaaaa".into()),
+                    published: Some(
+                        DateTime::parse_from_rfc3339("2021-12-18T20:00:00+00:00").unwrap()
+                    ),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rss() {
+        let feed = Feed::parse(MASTODON).unwrap();
+        assert_eq!(feed.title.as_deref(), Some("LoadingReadyRun"));
+        assert_eq!(
+            feed.entries,
+            vec![Entry {
+                id: Some("https://mastodon.example/@loadingreadyrun/107480000000000000".into()),
+                title: None,
+                link: Some("https://mastodon.example/@loadingreadyrun/107480000000000000".into()),
+                published: Some(
+                    DateTime::parse_from_rfc2822("Mon, 20 Dec 2021 21:00:00 +0000").unwrap()
+                ),
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_unknown() {
+        assert!(Feed::parse("<html></html>").is_err());
+    }
+
+    #[tokio::test]
+    async fn fetch() {
+        let url = serve_once(YOUTUBE).await;
+        let feed = Feeds::new(reqwest::Client::new()).fetch(&url).await.unwrap();
+        assert_eq!(feed, Feed::parse(YOUTUBE).unwrap());
+    }
+}
@@ -3,8 +3,8 @@ use anyhow::{Context, Error};
 use chrono::{DateTime, Duration, FixedOffset, NaiveDate, TimeZone};
 use chrono_tz::Tz;
 use reqwest::header::AUTHORIZATION;
-use reqwest::Client;
 use reqwest::Url;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use std::cmp;
 use std::path::PathBuf;
@@ -12,10 +12,13 @@ use std::sync::Arc;
 
 pub const LRR: &str = "loadingreadyrun.com_72jmf1fn564cbbr84l048pv1go@group.calendar.google.com";
 pub const FANSTREAMS: &str = "caffeinatedlemur@gmail.com";
+/// `Calendar::get_upcoming_events` returns at most this many events.
+pub const MAX_RESULTS: usize = 10;
 const SCOPES: &[&str] = &["https://www.googleapis.com/auth/calendar.events.readonly"];
 
 #[derive(Debug)]
 pub struct Event {
+    pub id: String,
     pub start: DateTime<FixedOffset>,
     pub summary: String,
     pub end: DateTime<FixedOffset>,
@@ -26,6 +29,7 @@ pub struct Event {
 impl Event {
     fn from_api_event(event: ApiEvent, timezone: Tz) -> Option<Self> {
         Some(Self {
+            id: event.id,
             start: event.start.resolve_datetime(timezone)?,
             summary: event.summary,
             end: event.end.resolve_datetime(timezone)?,
@@ -57,6 +61,7 @@ struct ListEventsResponse {
 
 #[derive(Deserialize)]
 struct ApiEvent {
+    pub id: String,
     pub start: Time,
     pub summary: String,
     pub end: Time,
@@ -134,7 +139,7 @@ impl Calendar {
             .get(url)
             .header(AUTHORIZATION, token)
             .query(&ListEventsRequest {
-                max_results: 10,
+                max_results: MAX_RESULTS,
                 order_by: "startTime",
                 single_events: true,
                 time_min: after,
@@ -151,6 +156,60 @@ impl Calendar {
         Ok(res.items.into_iter().flat_map(|event| Event::from_api_event(event, timezone)).collect())
     }
 
+    /// Gets a single event, or `None` if it has been deleted. `timezone` is used for all-day
+    /// events that don't have their own.
+    pub async fn get_event(
+        &self,
+        calendar: &str,
+        event_id: &str,
+        timezone: Tz,
+    ) -> Result<Option<Event>, Error> {
+        let url = {
+            let mut url = Url::parse("https://www.googleapis.com/calendar/v3/calendars")
+                .context("failed to parse the base URL")?;
+            {
+                let mut path_segments = url
+                    .path_segments_mut()
+                    .map_err(|()| Error::msg("https URL is cannot-be-a-base?"))?;
+                path_segments.push(calendar);
+                path_segments.push("events");
+                path_segments.push(event_id);
+            }
+
+            url
+        };
+
+        let token = self
+            .oauth2
+            .get_token()
+            .await
+            .context("failed to get a service account OAuth2 token")?;
+
+        let res = self
+            .client
+            .get(url)
+            .header(AUTHORIZATION, token)
+            .send()
+            .await
+            .context("failed to get the calendar event")?;
+        if res.status() == StatusCode::NOT_FOUND || res.status() == StatusCode::GONE {
+            return Ok(None);
+        }
+        // Deleted events can linger as cancelled ones with little more than their ID.
+        let event = res
+            .error_for_status()
+            .context("request failed")?
+            .json::<serde_json::Value>()
+            .await
+            .context("failed to parse the calendar event")?;
+        if event.get("status").and_then(|status| status.as_str()) == Some("cancelled") {
+            return Ok(None);
+        }
+        let event = serde_json::from_value::<ApiEvent>(event)
+            .context("failed to parse the calendar event")?;
+        Ok(Event::from_api_event(event, timezone))
+    }
+
     pub fn get_next_event<Tz: TimeZone>(
         events: &[Event],
         at: DateTime<Tz>,
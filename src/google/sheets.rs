@@ -254,4 +254,66 @@ impl Sheets {
 
         Ok(())
     }
+
+    /// Sets the cells in `row` starting from `column` to `values`.
+    pub async fn update_row_cells<'a>(
+        &'a self,
+        spreadsheet: &'a str,
+        sheet_id: u64,
+        row: u64,
+        column: u64,
+        values: &'a [&'a str],
+    ) -> Result<(), Error> {
+        let token = self
+            .oauth2
+            .get_token()
+            .await
+            .context("failed to get a service account OAuth2 token")?;
+
+        let url = {
+            let mut url = Url::parse("https://sheets.googleapis.com/v4/spreadsheets")
+                .context("failed to parse the base URL")?;
+            {
+                let mut path_segments = url
+                    .path_segments_mut()
+                    .map_err(|()| Error::msg("https URL is cannot-be-a-base?"))?;
+                let mut segment = String::from(spreadsheet);
+                segment.push_str(":batchUpdate");
+                path_segments.push(&segment);
+            }
+            url
+        };
+
+        let cells = values
+            .iter()
+            .map(|value| json!({ "userEnteredValue": { "stringValue": value } }))
+            .collect::<Vec<_>>();
+
+        self.client
+            .post(url)
+            .header(AUTHORIZATION, token)
+            .json(&json!({
+                "requests": [
+                    {
+                        "updateCells": {
+                            "rows": [{ "values": cells }],
+                            "fields": "userEnteredValue",
+                            "start": {
+                                "sheetId": sheet_id,
+                                "rowIndex": row,
+                                "columnIndex": column,
+                            }
+                        }
+                    }
+                ],
+                "includeSpreadsheetInResponse": false,
+            }))
+            .send()
+            .await
+            .context("failed to send the request")?
+            .error_for_status()
+            .context("request failed")?;
+
+        Ok(())
+    }
 }
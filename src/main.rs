@@ -16,20 +16,27 @@ use tracing::{error, info};
 mod aiomas;
 mod announcements;
 mod autotopic;
+mod channel_expiry;
 mod channel_reaper;
 mod commands;
 mod config;
 mod contact;
 mod context;
 mod desertbus;
+mod desertbus_history;
+mod discord_api;
 mod discord_events;
+mod emoji;
 mod extract;
+mod feed;
 mod google;
 mod influxdb;
 mod inventory;
+mod mastodon;
 mod models;
 mod pg_fts;
 mod rpc;
+mod scheduled_events;
 mod schema;
 mod service;
 mod shorten;
@@ -108,6 +115,8 @@ async fn main() -> Result<(), Error> {
         diesel::pg::PgConnection,
     >::new(&config.database_url[..]))
     .context("failed to create the database pool")?;
+    models::create_tables(&pg_pool.get().context("failed to get a database connection")?)
+        .context("failed to create the database tables")?;
 
     let http_client = reqwest::ClientBuilder::new()
         .user_agent(concat!(
@@ -130,6 +139,10 @@ async fn main() -> Result<(), Error> {
 
     let desertbus = desertbus::DesertBus::new(http_client.clone());
 
+    let feeds = feed::Feeds::new(http_client.clone());
+
+    let mastodon = mastodon::Mastodon::new(http_client.clone(), config.mastodon_api_base.clone());
+
     let twitter = crate::twitter::Twitter::new(
         http_client.clone(),
         config.twitter_api_key.clone(),
@@ -148,84 +161,87 @@ async fn main() -> Result<(), Error> {
     }
 
     let http = serenity::http::Http::new_with_token(&config.discord_botsecret);
+    let discord_api = discord_api::DiscordApi::new(http_client.clone(), http.token.clone());
+    let scheduled_events = scheduled_events::ScheduledEvents::new(discord_api.clone());
+    let emoji_cache = emoji::EmojiCache::new(discord_api);
     let current_application_info = http
         .get_current_application_info()
         .await
         .context("failed to get the current application info")?;
 
+    let mut framework = serenity::framework::StandardFramework::new()
+        .configure(|c| {
+            c.prefix(&config.command_prefix)
+                .with_whitespace((true, true, true))
+                .on_mention(Some(current_application_info.id))
+                .case_insensitivity(true)
+                .owners(
+                    [
+                        // Defrost#0001
+                        UserId(101919755132227584),
+                        // phlip#6324
+                        UserId(153674140019064832),
+                        // qrpth#6704
+                        UserId(144128240389324800),
+                    ]
+                    .iter()
+                    .copied()
+                    .collect(),
+                )
+        })
+        .before(|_, message, command_name| {
+            Box::pin(async move {
+                info!(
+                    command_name = command_name,
+                    message = message.content.as_str(),
+                    message.id = message.id.0,
+                    from.id = message.author.id.0,
+                    from.name = message.author.name.as_str(),
+                    from.discriminator = message.author.discriminator,
+                    "Command received",
+                );
+                true
+            })
+        })
+        .after(|ctx, message, _command_name, result| {
+            Box::pin(async move {
+                if let Err(error) = result {
+                    error!(
+                        message.id = message.id.0,
+                        ?error,
+                        "Command resulted in an unexpected error"
+                    );
+
+                    let _ = message.reply(
+                        ctx,
+                        &format!("Command resulted in an unexpected error: {}.", error),
+                    );
+                } else {
+                    info!(message.id = message.id.0, "Command processed successfully",);
+                }
+            })
+        })
+        .unrecognised_command(commands::static_response::static_response)
+        .help(&crate::commands::help::HELP);
+    for group in crate::commands::GROUPS {
+        framework.group_add(group);
+    }
+
+    let mut intents = GatewayIntents::GUILDS
+        | GatewayIntents::GUILD_MEMBERS
+        | GatewayIntents::GUILD_EMOJIS
+        | GatewayIntents::GUILD_VOICE_STATES
+        | GatewayIntents::GUILD_MESSAGES
+        | GatewayIntents::DIRECT_MESSAGES;
+    if config.voice_lobby.is_some() {
+        // Channels created from the lobby are named after the game the user is playing.
+        intents |= GatewayIntents::GUILD_PRESENCES;
+    }
+
     let mut client = serenity::Client::builder(&config.discord_botsecret)
-        .intents(
-            GatewayIntents::GUILDS
-                | GatewayIntents::GUILD_MEMBERS
-                | GatewayIntents::GUILD_EMOJIS
-                | GatewayIntents::GUILD_VOICE_STATES
-                | GatewayIntents::GUILD_MESSAGES
-                | GatewayIntents::DIRECT_MESSAGES,
-        )
+        .intents(intents)
         .event_handler(crate::discord_events::DiscordEvents::new())
-        .framework(
-            serenity::framework::StandardFramework::new()
-                .configure(|c| {
-                    c.prefix(&config.command_prefix)
-                        .with_whitespace((true, true, true))
-                        .on_mention(Some(current_application_info.id))
-                        .case_insensitivity(true)
-                        .owners(
-                            [
-                                // Defrost#0001
-                                UserId(101919755132227584),
-                                // phlip#6324
-                                UserId(153674140019064832),
-                                // qrpth#6704
-                                UserId(144128240389324800),
-                            ]
-                            .iter()
-                            .copied()
-                            .collect(),
-                        )
-                })
-                .before(|_, message, command_name| {
-                    Box::pin(async move {
-                        info!(
-                            command_name = command_name,
-                            message = message.content.as_str(),
-                            message.id = message.id.0,
-                            from.id = message.author.id.0,
-                            from.name = message.author.name.as_str(),
-                            from.discriminator = message.author.discriminator,
-                            "Command received",
-                        );
-                        true
-                    })
-                })
-                .after(|ctx, message, _command_name, result| {
-                    Box::pin(async move {
-                        if let Err(error) = result {
-                            error!(
-                                message.id = message.id.0,
-                                ?error,
-                                "Command resulted in an unexpected error"
-                            );
-
-                            let _ = message.reply(
-                                ctx,
-                                &format!("Command resulted in an unexpected error: {}.", error),
-                            );
-                        } else {
-                            info!(message.id = message.id.0, "Command processed successfully",);
-                        }
-                    })
-                })
-                .unrecognised_command(commands::static_response::static_response)
-                .help(&crate::commands::help::HELP)
-                .group(&crate::commands::calendar::CALENDAR_GROUP)
-                .group(&crate::commands::date::DATE_GROUP)
-                .group(&crate::commands::live::FANSTREAMS_GROUP)
-                .group(&crate::commands::quote::QUOTE_GROUP)
-                .group(&crate::commands::time::TIME_GROUP)
-                .group(&crate::commands::tracing::TRACING_GROUP)
-                .group(&crate::commands::voice::VOICE_GROUP),
-        )
+        .framework(framework)
         .type_map_insert::<crate::rpc::LRRbot>(std::sync::Arc::new(crate::rpc::LRRbot::new(
             &config,
         )))
@@ -242,7 +258,12 @@ async fn main() -> Result<(), Error> {
         .type_map_insert::<crate::google::Sheets>(spreadsheets)
         .type_map_insert::<crate::desertbus::DesertBus>(desertbus)
         .type_map_insert::<crate::twitter::Twitter>(twitter)
+        .type_map_insert::<crate::feed::Feeds>(feeds)
+        .type_map_insert::<crate::mastodon::Mastodon>(mastodon)
+        .type_map_insert::<crate::scheduled_events::ScheduledEvents>(scheduled_events)
         .type_map_insert::<crate::typemap_keys::ReloadHandle>(reload_handle)
+        .type_map_insert::<crate::emoji::EmojiCache>(emoji_cache)
+        .type_map_insert::<crate::commands::static_response::ResponseNames>(Default::default())
         .await
         .context("failed to create the Discord client")?;
 
@@ -267,7 +288,16 @@ async fn main() -> Result<(), Error> {
 
     tokio::spawn(rpc_server.serve());
     tokio::spawn(channel_reaper::channel_reaper(ctx.clone()));
+    tokio::spawn(channel_expiry::channel_expiry(ctx.clone()));
     tokio::spawn(announcements::post_tweets(ctx.clone()));
+    tokio::spawn(announcements::post_feeds(ctx.clone()));
+    tokio::spawn(announcements::post_statuses(ctx.clone()));
+    tokio::spawn(announcements::post_reminders(ctx.clone()));
+    tokio::spawn(announcements::post_schedule_changes(ctx.clone()));
+    tokio::spawn(announcements::post_desertbus_progress(ctx.clone()));
+    tokio::spawn(desertbus_history::record_runs(ctx.clone()));
+    tokio::spawn(scheduled_events::sync_scheduled_events(ctx.clone()));
+    tokio::spawn(announcements::update_stream_up_announcement(ctx.clone()));
     tokio::spawn(autotopic::autotopic(ctx.clone()));
     tokio::spawn(contact::post_messages(ctx));
 
@@ -0,0 +1,239 @@
+use anyhow::{anyhow, Context, Error};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// https://docs.joinmastodon.org/entities/account/
+#[derive(Clone, Debug, Deserialize)]
+pub struct Account {
+    pub id: String,
+    pub username: String,
+    /// `username` for local accounts, `username@instance` for remote ones.
+    pub acct: String,
+    pub display_name: String,
+}
+
+/// https://docs.joinmastodon.org/entities/status/
+#[derive(Debug, Deserialize)]
+pub struct Status {
+    pub id: String,
+    pub url: Option<String>,
+    pub account: Account,
+    pub in_reply_to_account_id: Option<String>,
+    pub reblog: Option<Box<Status>>,
+    pub mentions: Vec<Mention>,
+    pub content: String,
+}
+
+/// https://docs.joinmastodon.org/entities/Status/#Mention
+#[derive(Debug, Deserialize)]
+pub struct Mention {
+    pub id: String,
+    pub acct: String,
+}
+
+/// How many pages of statuses `Mastodon::account_statuses` fetches at most, in case an instance
+/// keeps returning full pages.
+const MAX_PAGES: usize = 25;
+
+#[derive(Serialize)]
+struct StatusesRequest<'a> {
+    limit: u32,
+    since_id: Option<&'a str>,
+    max_id: Option<&'a str>,
+}
+
+/// Splits `user@instance` into its parts.
+pub fn split_acct(acct: &str) -> Result<(&str, &str), Error> {
+    acct.split_once('@')
+        .filter(|(user, instance)| !user.is_empty() && !instance.is_empty())
+        .ok_or_else(|| anyhow!("{:?} is not of the form `user@instance`", acct))
+}
+
+/// Qualifies an `acct` as returned by `instance` with the instance's domain.
+pub fn full_acct(acct: &str, instance: &str) -> String {
+    if acct.contains('@') {
+        acct.to_lowercase()
+    } else {
+        format!("{}@{}", acct, instance).to_lowercase()
+    }
+}
+
+#[derive(Clone)]
+pub struct Mastodon {
+    client: Client,
+    api_base: Option<Url>,
+}
+
+impl Mastodon {
+    /// Requests go to the account's own instance unless `api_base` overrides it.
+    pub fn new(client: Client, api_base: Option<Url>) -> Mastodon {
+        Mastodon { client, api_base }
+    }
+
+    fn endpoint(&self, instance: &str, path: &str) -> Result<Url, Error> {
+        let base = match self.api_base {
+            Some(ref base) => base.clone(),
+            None => Url::parse(&format!("https://{}/", instance))
+                .with_context(|| format!("invalid instance {:?}", instance))?,
+        };
+        base.join(path).context("failed to build the endpoint URL")
+    }
+
+    pub async fn lookup_account(&self, acct: &str) -> Result<Account, Error> {
+        let (user, instance) = split_acct(acct)?;
+        let account = self
+            .client
+            .get(self.endpoint(instance, "api/v1/accounts/lookup")?)
+            .query(&[("acct", user)])
+            .send()
+            .await
+            .context("failed to send the account lookup request")?
+            .error_for_status()
+            .context("account lookup request failed")?
+            .json::<Account>()
+            .await
+            .context("failed to parse the account")?;
+        Ok(account)
+    }
+
+    /// The statuses newer than `since_id`, newest first, fetched `limit` at a time. Without
+    /// `since_id`, only the newest `limit` statuses.
+    pub async fn account_statuses(
+        &self,
+        instance: &str,
+        account_id: &str,
+        limit: u32,
+        since_id: Option<&str>,
+    ) -> Result<Vec<Status>, Error> {
+        let endpoint =
+            self.endpoint(instance, &format!("api/v1/accounts/{}/statuses", account_id))?;
+        let mut statuses = Vec::<Status>::new();
+
+        for _ in 0..MAX_PAGES {
+            let max_id = statuses.last().map(|status| status.id.as_str());
+            let page = self
+                .client
+                .get(endpoint.clone())
+                .query(&StatusesRequest { limit, since_id, max_id })
+                .send()
+                .await
+                .context("failed to send the statuses request")?
+                .error_for_status()
+                .context("statuses request failed")?
+                .json::<Vec<Status>>()
+                .await
+                .context("failed to parse the statuses")?;
+            let done = since_id.is_none() || page.len() < limit as usize;
+            statuses.extend(page);
+            if done {
+                break;
+            }
+        }
+
+        Ok(statuses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{full_acct, split_acct, Mastodon};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use url::Url;
+
+    /// Serve canned JSON responses, keyed by request target, to `requests` requests.
+    async fn serve(routes: &'static [(&'static str, &'static str)], requests: usize) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("failed to bind");
+        let addr = listener.local_addr().expect("failed to get the local address");
+
+        tokio::spawn(async move {
+            for _ in 0..requests {
+                let (mut stream, _) = listener.accept().await.expect("failed to accept");
+                let mut request = vec![];
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.expect("failed to read the request");
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8_lossy(&request);
+                let target = request.split(' ').nth(1).unwrap_or_default();
+                let (status, body) = routes
+                    .iter()
+                    .find(|(route, _)| *route == target)
+                    .map(|(_, body)| ("200 OK", *body))
+                    .unwrap_or(("404 Not Found", "{}"));
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.expect("failed to write the response");
+            }
+        });
+
+        Url::parse(&format!("http://{}/", addr)).unwrap()
+    }
+
+    #[test]
+    fn accts() {
+        assert_eq!(
+            split_acct("loadingreadyrun@mastodon.example").unwrap(),
+            ("loadingreadyrun", "mastodon.example")
+        );
+        assert!(split_acct("loadingreadyrun").is_err());
+        assert!(split_acct("@mastodon.example").is_err());
+
+        assert_eq!(full_acct("LRR", "mastodon.example"), "lrr@mastodon.example");
+        assert_eq!(
+            full_acct("desertbus@other.example", "mastodon.example"),
+            "desertbus@other.example"
+        );
+    }
+
+    #[tokio::test]
+    async fn lookup_and_statuses() {
+        const ROUTES: &[(&str, &str)] = &[
+            (
+                "/api/v1/accounts/lookup?acct=loadingreadyrun",
+                include_str!("../fixtures/mastodon/account.json"),
+            ),
+            (
+                "/api/v1/accounts/1/statuses?limit=40&since_id=100",
+                include_str!("../fixtures/mastodon/statuses.json"),
+            ),
+            (
+                "/api/v1/accounts/1/statuses?limit=6&since_id=100",
+                include_str!("../fixtures/mastodon/statuses.json"),
+            ),
+            ("/api/v1/accounts/1/statuses?limit=6&since_id=100&max_id=101", "[]"),
+        ];
+        let base = serve(ROUTES, 4).await;
+        let mastodon = Mastodon::new(reqwest::Client::new(), Some(base));
+
+        let account = mastodon.lookup_account("loadingreadyrun@mastodon.example").await.unwrap();
+        assert_eq!(account.id, "1");
+        assert_eq!(account.display_name, "LoadingReadyRun");
+
+        let statuses = mastodon
+            .account_statuses("mastodon.example", &account.id, 40, Some("100"))
+            .await
+            .unwrap();
+        assert_eq!(
+            statuses.iter().map(|status| &status.id[..]).collect::<Vec<_>>(),
+            vec!["106", "105", "104", "103", "102", "101"]
+        );
+        assert!(statuses[1].reblog.is_some());
+
+        // A full page means there might be more.
+        let statuses = mastodon
+            .account_statuses("mastodon.example", &account.id, 6, Some("100"))
+            .await
+            .unwrap();
+        assert_eq!(statuses.len(), 6);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::schema::*;
 use anyhow::Error;
-use chrono::NaiveDate;
+use chrono::{DateTime, NaiveDate, Utc};
 use diesel::pg::upsert::excluded;
 use diesel::pg::Pg;
 use diesel::prelude::*;
@@ -8,6 +8,12 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::fmt::{Display, Formatter};
 
+/// Creates the tables that only Eris uses, as they're not part of LRRbot's schema. These are the
+/// `up.sql` files from `migrations/`, which are safe to run on every start.
+pub fn create_tables<C: Connection<Backend = Pg>>(conn: &C) -> QueryResult<()> {
+    conn.batch_execute(include_str!("../migrations/2022-01-10-000000_create_temp_channels/up.sql"))
+}
+
 #[derive(Identifiable, Debug, Queryable)]
 #[primary_key(game_id, show_id)]
 #[table_name = "game_per_show_data"]
@@ -122,6 +128,96 @@ impl State {
             .execute(conn)?;
         Ok(())
     }
+
+    pub fn delete<C: Connection<Backend = Pg>>(key: &str, conn: &C) -> Result<(), Error> {
+        diesel::delete(state::table.find(key)).execute(conn)?;
+        Ok(())
+    }
+
+    /// Changes the value of `key` with `f`, starting from the default if it isn't set. The row is
+    /// locked while it's being changed, so that concurrent changes don't get lost.
+    pub fn update<T, C, F>(key: &str, conn: &C, f: F) -> Result<(), Error>
+    where
+        T: for<'de> Deserialize<'de> + Serialize + Default,
+        C: Connection<Backend = Pg>,
+        F: FnOnce(&mut T),
+    {
+        conn.transaction::<_, Error, _>(|| {
+            // Make sure there's a row to lock.
+            diesel::insert_into(state::table)
+                .values(NewState { key, value: serde_json::to_value(T::default())? })
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            let value =
+                state::table.find(key).select(state::value).for_update().first::<Value>(conn)?;
+            let mut value = serde_json::from_value(value)?;
+            f(&mut value);
+            State::set(key, value, conn)
+        })
+    }
+}
+
+/// A temporary voice channel and who it belongs to. The IDs are Discord snowflakes.
+#[derive(Identifiable, Insertable, Debug, Clone, Queryable)]
+pub struct TempChannel {
+    pub id: i64,
+    pub owner_id: i64,
+    pub created_at: DateTime<Utc>,
+    /// When someone was last in the channel.
+    pub last_occupied_at: DateTime<Utc>,
+}
+
+impl TempChannel {
+    pub fn all<C: Connection<Backend = Pg>>(conn: &C) -> QueryResult<Vec<Self>> {
+        temp_channels::table.load(conn)
+    }
+
+    pub fn find<C: Connection<Backend = Pg>>(id: i64, conn: &C) -> QueryResult<Option<Self>> {
+        temp_channels::table.find(id).first(conn).optional()
+    }
+
+    pub fn owned_by<C: Connection<Backend = Pg>>(
+        owner_id: i64,
+        conn: &C,
+    ) -> QueryResult<Vec<Self>> {
+        temp_channels::table
+            .filter(temp_channels::owner_id.eq(owner_id))
+            .order(temp_channels::created_at)
+            .load(conn)
+    }
+
+    pub fn insert<C: Connection<Backend = Pg>>(&self, conn: &C) -> QueryResult<()> {
+        diesel::insert_into(temp_channels::table).values(self).execute(conn)?;
+        Ok(())
+    }
+
+    pub fn set_owner<C: Connection<Backend = Pg>>(
+        id: i64,
+        owner_id: i64,
+        conn: &C,
+    ) -> QueryResult<()> {
+        diesel::update(temp_channels::table.find(id))
+            .set(temp_channels::owner_id.eq(owner_id))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// Records that someone is or just was in the channel. Does nothing to untracked channels.
+    pub fn touch<C: Connection<Backend = Pg>>(
+        id: i64,
+        now: DateTime<Utc>,
+        conn: &C,
+    ) -> QueryResult<()> {
+        diesel::update(temp_channels::table.find(id))
+            .set(temp_channels::last_occupied_at.eq(now))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub fn delete<C: Connection<Backend = Pg>>(id: i64, conn: &C) -> QueryResult<()> {
+        diesel::delete(temp_channels::table.find(id)).execute(conn)?;
+        Ok(())
+    }
 }
 
 #[derive(Identifiable, Debug, Queryable)]
@@ -0,0 +1,416 @@
+//! Mirrors the upcoming events on the LRR calendar into Discord's guild scheduled events.
+//!
+//! Serenity doesn't know about scheduled events yet, so this talks to the API directly.
+
+use crate::config::Config;
+use crate::context::ErisContext;
+use crate::discord_api::DiscordApi;
+use crate::extract::Extract;
+use crate::google::calendar::{Event, LRR, MAX_RESULTS};
+use crate::google::Calendar;
+use crate::models::State;
+use crate::typemap_keys::PgPool;
+use anyhow::{Context, Error};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{error, info};
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Maps Google event IDs to the IDs of the scheduled events created for them.
+const STATE_KEY: &str = "eris.scheduled_events";
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+
+const PRIVACY_LEVEL_GUILD_ONLY: u8 = 2;
+const ENTITY_TYPE_EXTERNAL: u8 = 3;
+const STATUS_SCHEDULED: u8 = 1;
+const STATUS_CANCELED: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityMetadata {
+    pub location: Option<String>,
+}
+
+/// https://discord.com/developers/docs/resources/guild-scheduled-event
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduledEvent {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub scheduled_start_time: DateTime<FixedOffset>,
+    pub scheduled_end_time: Option<DateTime<FixedOffset>>,
+    pub entity_metadata: Option<EntityMetadata>,
+    pub status: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NewScheduledEvent {
+    pub name: String,
+    pub description: Option<String>,
+    pub scheduled_start_time: DateTime<FixedOffset>,
+    pub scheduled_end_time: DateTime<FixedOffset>,
+    pub entity_metadata: EntityMetadata,
+    pub privacy_level: u8,
+    pub entity_type: u8,
+}
+
+impl NewScheduledEvent {
+    fn from_calendar_event(event: &Event, location: &str) -> NewScheduledEvent {
+        let description = event.description.as_deref().map(|desc| {
+            let desc = Calendar::format_description(desc);
+            match desc.char_indices().nth(MAX_DESCRIPTION_LENGTH) {
+                Some((index, _)) => desc[..index].into(),
+                None => desc,
+            }
+        });
+
+        NewScheduledEvent {
+            name: event.summary.clone(),
+            description,
+            scheduled_start_time: event.start,
+            scheduled_end_time: event.end,
+            entity_metadata: EntityMetadata { location: Some(location.into()) },
+            privacy_level: PRIVACY_LEVEL_GUILD_ONLY,
+            entity_type: ENTITY_TYPE_EXTERNAL,
+        }
+    }
+
+    fn matches(&self, event: &ScheduledEvent) -> bool {
+        self.name == event.name
+            && self.description == event.description
+            && self.scheduled_start_time == event.scheduled_start_time
+            && Some(self.scheduled_end_time) == event.scheduled_end_time
+            && Some(&self.entity_metadata) == event.entity_metadata.as_ref()
+    }
+}
+
+#[derive(Serialize)]
+struct StatusUpdate {
+    status: u8,
+}
+
+#[derive(Clone)]
+pub struct ScheduledEvents {
+    api: DiscordApi,
+}
+
+impl ScheduledEvents {
+    pub fn new(api: DiscordApi) -> ScheduledEvents {
+        ScheduledEvents { api }
+    }
+
+    pub async fn list(&self, guild: GuildId) -> Result<Vec<ScheduledEvent>, Error> {
+        self.api
+            .get(&format!("/guilds/{}/scheduled-events", guild.0))
+            .await
+            .context("failed to list the scheduled events")
+    }
+
+    pub async fn create(
+        &self,
+        guild: GuildId,
+        event: &NewScheduledEvent,
+    ) -> Result<ScheduledEvent, Error> {
+        self.api
+            .post(&format!("/guilds/{}/scheduled-events", guild.0), event)
+            .await
+            .context("failed to create the scheduled event")
+    }
+
+    pub async fn modify<T: Serialize>(
+        &self,
+        guild: GuildId,
+        event_id: &str,
+        changes: &T,
+    ) -> Result<ScheduledEvent, Error> {
+        self.api
+            .patch(&format!("/guilds/{}/scheduled-events/{}", guild.0, event_id), changes)
+            .await
+            .context("failed to modify the scheduled event")
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Create {
+        google_id: String,
+        event: NewScheduledEvent,
+    },
+    Update {
+        google_id: String,
+        event_id: String,
+        event: NewScheduledEvent,
+    },
+    Cancel {
+        google_id: String,
+        event_id: String,
+    },
+    /// The scheduled event is gone, e.g. it was deleted by hand or has ended.
+    Forget {
+        google_id: String,
+    },
+}
+
+/// Works out how to bring the guild's scheduled events in line with the calendar.
+///
+/// `window_end` is how far into the future `events` is complete. Mirrored events starting after it
+/// may just have been pushed out of the fetched events, so they're left alone.
+fn plan(
+    events: &[Event],
+    location: &str,
+    mirrored: &HashMap<String, String>,
+    existing: &HashMap<String, ScheduledEvent>,
+    now: DateTime<Utc>,
+    window_end: Option<DateTime<FixedOffset>>,
+) -> Vec<Action> {
+    let mut actions = vec![];
+
+    for event in events {
+        let new_event = NewScheduledEvent::from_calendar_event(event, location);
+        let scheduled_event = mirrored.get(&event.id).and_then(|event_id| existing.get(event_id));
+        match scheduled_event {
+            Some(scheduled_event) => {
+                // Once an event is live only the Discord side gets to change it.
+                if scheduled_event.status == STATUS_SCHEDULED && !new_event.matches(scheduled_event)
+                {
+                    actions.push(Action::Update {
+                        google_id: event.id.clone(),
+                        event_id: scheduled_event.id.clone(),
+                        event: new_event,
+                    });
+                }
+            }
+            None => {
+                if mirrored.contains_key(&event.id) {
+                    actions.push(Action::Forget { google_id: event.id.clone() });
+                }
+                // Discord doesn't allow scheduling events in the past.
+                if event.start > now {
+                    actions.push(Action::Create { google_id: event.id.clone(), event: new_event });
+                }
+            }
+        }
+    }
+
+    for (google_id, event_id) in mirrored {
+        if events.iter().any(|event| event.id == *google_id) {
+            continue;
+        }
+
+        match existing.get(event_id) {
+            Some(scheduled_event) => {
+                let in_window = window_end
+                    .map(|end| scheduled_event.scheduled_start_time <= end)
+                    .unwrap_or(true);
+                if scheduled_event.status == STATUS_SCHEDULED
+                    && scheduled_event.scheduled_start_time > now
+                    && in_window
+                {
+                    actions.push(Action::Cancel {
+                        google_id: google_id.clone(),
+                        event_id: event_id.clone(),
+                    });
+                }
+            }
+            None => actions.push(Action::Forget { google_id: google_id.clone() }),
+        }
+    }
+
+    actions
+}
+
+async fn sync(ctx: &ErisContext) -> Result<(), Error> {
+    let data = ctx.data.read().await;
+    let config = data.extract::<Config>()?;
+    let calendar = data.extract::<Calendar>()?;
+    let scheduled_events = data.extract::<ScheduledEvents>()?;
+
+    let now = Utc::now();
+    let events = calendar
+        .get_upcoming_events(LRR, now)
+        .await
+        .context("failed to get the upcoming events")?;
+    let window_end =
+        if events.len() < MAX_RESULTS { None } else { events.last().map(|event| event.start) };
+
+    let existing = scheduled_events
+        .list(config.guild)
+        .await?
+        .into_iter()
+        .map(|event| (event.id.clone(), event))
+        .collect::<HashMap<_, _>>();
+
+    let mut mirrored = {
+        let conn = data
+            .extract::<PgPool>()?
+            .get()
+            .context("failed to get a DB connection from the connection pool")?;
+
+        State::get::<HashMap<String, String>, _>(STATE_KEY, &conn)
+            .context("failed to get the mirrored events")?
+            .unwrap_or_default()
+    };
+
+    let location = format!("https://twitch.tv/{}", config.channel);
+    let actions = plan(&events, &location, &mirrored, &existing, now, window_end);
+    if actions.is_empty() {
+        return Ok(());
+    }
+
+    for action in actions {
+        let result = match action {
+            Action::Create { google_id, event } => {
+                info!(google_id = google_id.as_str(), "Creating a scheduled event");
+                scheduled_events.create(config.guild, &event).await.map(|scheduled_event| {
+                    mirrored.insert(google_id, scheduled_event.id);
+                })
+            }
+            Action::Update { google_id, event_id, event } => {
+                info!(google_id = google_id.as_str(), "Updating a scheduled event");
+                scheduled_events.modify(config.guild, &event_id, &event).await.map(|_| ())
+            }
+            Action::Cancel { google_id, event_id } => {
+                info!(google_id = google_id.as_str(), "Cancelling a scheduled event");
+                let status = StatusUpdate { status: STATUS_CANCELED };
+                scheduled_events.modify(config.guild, &event_id, &status).await.map(|_| {
+                    mirrored.remove(&google_id);
+                })
+            }
+            Action::Forget { google_id } => {
+                mirrored.remove(&google_id);
+                Ok(())
+            }
+        };
+
+        if let Err(error) = result {
+            error!(?error, "Failed to update the scheduled events");
+        }
+    }
+
+    let conn = data
+        .extract::<PgPool>()?
+        .get()
+        .context("failed to get a DB connection from the connection pool")?;
+    State::set(STATE_KEY, &mirrored, &conn).context("failed to save the mirrored events")?;
+
+    Ok(())
+}
+
+pub async fn sync_scheduled_events(ctx: ErisContext) {
+    let mut timer = tokio::time::interval(SYNC_INTERVAL);
+
+    loop {
+        timer.tick().await;
+
+        if let Err(error) = sync(&ctx).await {
+            error!(?error, "Failed to sync the scheduled events");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plan, Action, NewScheduledEvent, ScheduledEvent, STATUS_SCHEDULED};
+    use crate::google::calendar::Event;
+    use chrono::{DateTime, Duration, Utc};
+    use std::collections::HashMap;
+
+    const LOCATION: &str = "https://twitch.tv/loadingreadyrun";
+
+    fn event(id: &str, start: &str) -> Event {
+        let start = DateTime::parse_from_rfc3339(start).unwrap();
+        Event {
+            id: id.into(),
+            start,
+            summary: format!("Stream {}", id),
+            end: start + Duration::hours(2),
+            location: None,
+            description: None,
+        }
+    }
+
+    fn scheduled(id: &str, event: &Event) -> ScheduledEvent {
+        let new_event = NewScheduledEvent::from_calendar_event(event, LOCATION);
+        ScheduledEvent {
+            id: id.into(),
+            name: new_event.name,
+            description: new_event.description,
+            scheduled_start_time: new_event.scheduled_start_time,
+            scheduled_end_time: Some(new_event.scheduled_end_time),
+            entity_metadata: Some(new_event.entity_metadata),
+            status: STATUS_SCHEDULED,
+        }
+    }
+
+    #[test]
+    fn sync_plan() {
+        let now = DateTime::parse_from_rfc3339("2021-12-20T12:00:00Z").unwrap().with_timezone(&Utc);
+        let events = vec![
+            event("unchanged", "2021-12-20T20:00:00Z"),
+            event("moved", "2021-12-21T20:00:00Z"),
+            event("new", "2021-12-22T20:00:00Z"),
+            event("deleted", "2021-12-23T20:00:00Z"),
+        ];
+        let removed = event("removed", "2021-12-21T18:00:00Z");
+
+        let mut mirrored = HashMap::new();
+        let mut existing = HashMap::new();
+        for (google_id, event_id, event) in &[
+            ("unchanged", "1", &events[0]),
+            ("moved", "2", &event("moved", "2021-12-21T19:00:00Z")),
+            ("removed", "3", &removed),
+        ] {
+            mirrored.insert(google_id.to_string(), event_id.to_string());
+            existing.insert(event_id.to_string(), scheduled(event_id, event));
+        }
+        mirrored.insert(String::from("deleted"), String::from("4"));
+
+        let mut actions = plan(&events, LOCATION, &mirrored, &existing, now, None);
+        actions.sort_by_key(|action| format!("{:?}", action));
+        assert_eq!(
+            actions,
+            vec![
+                Action::Cancel { google_id: "removed".into(), event_id: "3".into() },
+                Action::Create {
+                    google_id: "deleted".into(),
+                    event: NewScheduledEvent::from_calendar_event(&events[3], LOCATION),
+                },
+                Action::Create {
+                    google_id: "new".into(),
+                    event: NewScheduledEvent::from_calendar_event(&events[2], LOCATION),
+                },
+                Action::Forget { google_id: "deleted".into() },
+                Action::Update {
+                    google_id: "moved".into(),
+                    event_id: "2".into(),
+                    event: NewScheduledEvent::from_calendar_event(&events[1], LOCATION),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn outside_window() {
+        let now = DateTime::parse_from_rfc3339("2021-12-20T12:00:00Z").unwrap().with_timezone(&Utc);
+        let events = vec![event("first", "2021-12-20T20:00:00Z")];
+        let later = event("later", "2021-12-30T20:00:00Z");
+
+        let mut mirrored = HashMap::new();
+        mirrored.insert(String::from("first"), String::from("1"));
+        mirrored.insert(String::from("later"), String::from("2"));
+        let mut existing = HashMap::new();
+        existing.insert(String::from("1"), scheduled("1", &events[0]));
+        existing.insert(String::from("2"), scheduled("2", &later));
+
+        assert_eq!(
+            plan(&events, LOCATION, &mirrored, &existing, now, Some(events[0].start)),
+            vec![]
+        );
+        assert_eq!(
+            plan(&events, LOCATION, &mirrored, &existing, now, None),
+            vec![Action::Cancel { google_id: "later".into(), event_id: "2".into() }]
+        );
+    }
+}
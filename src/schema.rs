@@ -42,6 +42,15 @@ table! {
     }
 }
 
+table! {
+    temp_channels (id) {
+        id -> Int8,
+        owner_id -> Int8,
+        created_at -> Timestamptz,
+        last_occupied_at -> Timestamptz,
+    }
+}
+
 table! {
     users (id) {
         id -> Int4,
@@ -64,4 +73,12 @@ joinable!(game_per_show_data -> shows (show_id));
 joinable!(quotes -> games (game_id));
 joinable!(quotes -> shows (show_id));
 
-allow_tables_to_appear_in_same_query!(game_per_show_data, games, quotes, shows, state, users,);
+allow_tables_to_appear_in_same_query!(
+    game_per_show_data,
+    games,
+    quotes,
+    shows,
+    state,
+    temp_channels,
+    users,
+);
@@ -9,6 +9,43 @@ impl HumanReadable {
     }
 }
 
+/// The longest duration `parse_duration` accepts, well within what `chrono` can represent.
+const MAX_DURATION_SECONDS: i64 = 10 * 366 * 24 * 60 * 60;
+
+/// Parses durations in the format `HumanReadable` displays them in, like `1d12h` or `30m`.
+///
+/// Anything longer than about ten years is rejected.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let mut total = 0i64;
+    let mut number = None::<i64>;
+
+    for c in s.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = Some(number.unwrap_or(0).checked_mul(10)?.checked_add(i64::from(digit))?);
+            continue;
+        }
+
+        let n = number.take()?;
+        let unit = match c {
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        total = total.checked_add(n.checked_mul(unit)?)?;
+        if total > MAX_DURATION_SECONDS {
+            return None;
+        }
+    }
+
+    if number.is_some() || s.is_empty() {
+        None
+    } else {
+        Some(Duration::seconds(total))
+    }
+}
+
 impl Display for HumanReadable {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         let mut d = self.0;
@@ -45,3 +82,27 @@ impl Display for HumanReadable {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_duration, HumanReadable};
+    use chrono::Duration;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("30m"), Some(Duration::minutes(30)));
+        assert_eq!(parse_duration("1d12h"), Some(Duration::hours(36)));
+        assert_eq!(parse_duration("90s"), Some(Duration::seconds(90)));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("30"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("soon"), None);
+        assert_eq!(parse_duration("3650d"), Some(Duration::days(3650)));
+        assert_eq!(parse_duration("100000000d"), None);
+        assert_eq!(parse_duration("9223372036854775807s"), None);
+        assert_eq!(parse_duration("3000d3000d"), None);
+
+        let duration = Duration::days(2) + Duration::hours(3) + Duration::minutes(4);
+        assert_eq!(parse_duration(&HumanReadable::new(duration).to_string()), Some(duration));
+    }
+}
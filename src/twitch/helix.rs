@@ -14,7 +14,7 @@ pub enum UserId<'a> {
 #[derive(Copy, Clone, Debug)]
 pub enum GameId<'a> {
     Id(&'a str),
-    // Name(&'a str),
+    Name(&'a str),
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -88,7 +88,7 @@ impl FillParams for GameId<'_> {
     fn fill_params<'a>(&'a self, params: &mut Vec<(&'a str, &'a str)>) {
         match *self {
             GameId::Id(id) => params.push(("id", id)),
-            // GameId::Name(name) => params.push(("name", name)),
+            GameId::Name(name) => params.push(("name", name)),
         }
     }
 }
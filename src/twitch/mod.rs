@@ -1,3 +1,4 @@
+pub mod fanstreams;
 pub mod helix;
 
 pub use self::helix::Helix;
@@ -29,6 +29,7 @@ struct UserTimelineRequest {
     include_rts: bool,
     count: u32,
     since_id: Option<u64>,
+    tweet_mode: &'static str,
 }
 
 /// https://developer.twitter.com/en/docs/tweets/data-dictionary/overview/tweet-object
@@ -36,8 +37,12 @@ struct UserTimelineRequest {
 pub struct Tweet {
     pub id: u64,
     pub user: User,
+    #[serde(alias = "text")]
+    pub full_text: String,
     pub in_reply_to_user_id: Option<u64>,
     pub retweeted_status: Option<Box<Tweet>>,
+    #[serde(default)]
+    pub is_quote_status: bool,
     pub entities: Entities,
 }
 
@@ -128,6 +133,7 @@ impl Twitter {
                 include_rts: with_retweets,
                 count,
                 since_id,
+                tweet_mode: "extended",
             })
             .send()
             .await
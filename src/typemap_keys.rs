@@ -1,8 +1,13 @@
+use crate::commands::static_response::ResponseNames;
 use crate::config::Config;
 use crate::desertbus::DesertBus;
+use crate::emoji::EmojiCache;
+use crate::feed::Feeds;
 use crate::google::{Calendar, Sheets};
 use crate::influxdb::InfluxDB;
+use crate::mastodon::Mastodon;
 use crate::rpc::LRRbot;
+use crate::scheduled_events::ScheduledEvents;
 use crate::twitch::Helix;
 use crate::twitter::Twitter;
 use serenity::prelude::TypeMapKey;
@@ -22,6 +27,14 @@ impl TypeMapKey for DesertBus {
     type Value = Self;
 }
 
+impl TypeMapKey for EmojiCache {
+    type Value = Self;
+}
+
+impl TypeMapKey for Feeds {
+    type Value = Self;
+}
+
 impl TypeMapKey for Helix {
     type Value = Self;
 }
@@ -38,6 +51,18 @@ impl TypeMapKey for Sheets {
     type Value = Self;
 }
 
+impl TypeMapKey for Mastodon {
+    type Value = Self;
+}
+
+impl TypeMapKey for ResponseNames {
+    type Value = Self;
+}
+
+impl TypeMapKey for ScheduledEvents {
+    type Value = Self;
+}
+
 impl TypeMapKey for Twitter {
     type Value = Self;
 }